@@ -1,11 +1,277 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
 use std::env;
+use std::fmt::Write;
+use std::fs;
 use std::path::Path;
 
+struct Asset {
+    path: String,
+    offset: usize,
+    size: usize,
+}
+
+fn read_u32(image: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+// Walks a dir in the image made by py/create_fs.py, see doc/fs.txt
+fn collect_assets(image: &[u8], begin: usize, prefix: &str, assets: &mut Vec<Asset>) {
+    let mut index = begin;
+    loop {
+        let name_size = usize::from(image[index]);
+        if name_size == 0 {
+            break;
+        }
+        let name = String::from_utf8_lossy(&image[index + 1..index + 1 + name_size]);
+        index = align4(index + 1 + name_size);
+        let flag_size = read_u32(image, index);
+        index += 4;
+        if flag_size & 0x4000000 != 0 {
+            index += 4 + read_u32(image, index);
+        }
+        let size = flag_size & 0x1FFFFFF;
+        let path = format!("{}{}", prefix, name);
+        if flag_size & 0x8000000 != 0 {
+            assets.push(Asset {
+                path,
+                offset: read_u32(image, index),
+                size,
+            });
+            index += 4;
+        } else if flag_size & 0x2000000 != 0 {
+            collect_assets(image, index, &format!("{}/", path), assets);
+            index += size;
+        } else {
+            assets.push(Asset {
+                path,
+                offset: index,
+                size,
+            });
+            index += size;
+        }
+        index = align4(index);
+    }
+}
+
+fn asset_kind(path: &str) -> Option<&'static str> {
+    match Path::new(path).extension()?.to_str()? {
+        "img" => Some("Image"),
+        "txt" => Some("Text"),
+        "sfx" => Some("SoundEffect"),
+        "mod" | "xm" => Some("Music"),
+        "wav" => Some("Sound"),
+        "krec" => Some("Recording"),
+        _ => None,
+    }
+}
+
+// Known kinds drop their extension, so img/gba_yeen.img is IMG_GBA_YEEN
+fn asset_const_name(path: &str) -> String {
+    let path = match asset_kind(path) {
+        Some(_) => &path[..path.rfind('.').unwrap()],
+        None => path,
+    };
+    let mut name: String = path
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn write_assets(manifest_dir: &Path) {
+    let image_path = manifest_dir.join("data.bin");
+    println!("cargo:rerun-if-changed={}", image_path.display());
+    let image = fs::read(&image_path)
+        .expect("data.bin is missing, run py/create_fs.py data data.bin before building");
+
+    let mut assets = Vec::new();
+    collect_assets(&image, 0, "", &mut assets);
+    assets.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut names = HashMap::new();
+    let mut out = String::new();
+    for asset in assets.iter() {
+        let name = asset_const_name(&asset.path);
+        if let Some(other) = names.insert(name.clone(), &asset.path) {
+            panic!("Assets {} and {} both map to {}", other, asset.path, name);
+        }
+        // Not every asset is used by the code
+        writeln!(
+            out,
+            "#[allow(dead_code)]\npub const {}: Asset = Asset {{ path: {:?}, offset: {:#x}, size: {:#x}, kind: AssetKind::{} }};",
+            name,
+            asset.path,
+            asset.offset,
+            asset.size,
+            asset_kind(&asset.path).unwrap_or("Binary")
+        )
+        .unwrap();
+    }
+    fs::write(
+        Path::new(&env::var("OUT_DIR").unwrap()).join("assets.rs"),
+        out,
+    )
+    .unwrap();
+}
+
+struct Header {
+    title: String,
+    game_code: String,
+    maker_code: String,
+    version: u8,
+}
+
+fn is_title_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || " '-.!&".contains(c)
+}
+
+fn is_code_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit()
+}
+
+// Environment variables override [package.metadata.gba] in Cargo.toml
+fn header_field(metadata: Option<&toml::Value>, key: &str, var: &str) -> Option<toml::Value> {
+    println!("cargo:rerun-if-env-changed={}", var);
+    match env::var(var) {
+        Ok(value) => Some(toml::Value::String(value)),
+        Err(_) => metadata.and_then(|metadata| metadata.get(key)).cloned(),
+    }
+}
+
+fn header_string(metadata: Option<&toml::Value>, key: &str, var: &str) -> String {
+    match header_field(metadata, key, var) {
+        Some(toml::Value::String(value)) => value,
+        Some(_) => panic!("package.metadata.gba.{} must be a string", key),
+        None => panic!("Set package.metadata.gba.{} in Cargo.toml or {}", key, var),
+    }
+}
+
+fn read_header(manifest_dir: &Path) -> Header {
+    let manifest_path = manifest_dir.join("Cargo.toml");
+    println!("cargo:rerun-if-changed={}", manifest_path.display());
+    let manifest: toml::Value = fs::read_to_string(&manifest_path).unwrap().parse().unwrap();
+    let metadata = manifest
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("gba"));
+
+    let title = header_string(metadata, "title", "GBA_TITLE");
+    let game_code = header_string(metadata, "game-code", "GBA_GAME_CODE");
+    let maker_code = header_string(metadata, "maker-code", "GBA_MAKER_CODE");
+    let version = match header_field(metadata, "version", "GBA_VERSION") {
+        Some(toml::Value::Integer(version)) => version,
+        Some(toml::Value::String(version)) => version.parse().unwrap_or(-1),
+        Some(_) => -1,
+        None => 0,
+    };
+
+    if title.len() > 12 || !title.chars().all(is_title_char) {
+        panic!(
+            "Title {:?} must be up to 12 upper case letters, digits or \" '-.!&\"",
+            title
+        );
+    }
+    if game_code.len() != 4 || !game_code.chars().all(is_code_char) {
+        panic!(
+            "Game code {:?} must be 4 upper case letters or digits",
+            game_code
+        );
+    }
+    if maker_code.len() != 2 || !maker_code.chars().all(is_code_char) {
+        panic!(
+            "Maker code {:?} must be 2 upper case letters or digits",
+            maker_code
+        );
+    }
+    if !(0..=255).contains(&version) {
+        panic!("Version must be from 0 to 255");
+    }
+
+    Header {
+        title,
+        game_code,
+        maker_code,
+        version: version as u8,
+    }
+}
+
+// Writes the header fields from the title to the complement check, 0xA0 to 0xBD in the rom
+fn write_header(header: &Header, out_dir: &Path) {
+    let mut fields = Vec::new();
+    fields.extend_from_slice(header.title.as_bytes());
+    fields.resize(12, 0);
+    fields.extend_from_slice(header.game_code.as_bytes());
+    fields.extend_from_slice(header.maker_code.as_bytes());
+    fields.extend_from_slice(&[0x96, 0, 0, 0, 0, 0, 0, 0, 0, 0, header.version]);
+    let checksum = fields
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_sub(*byte))
+        .wrapping_sub(0x19);
+
+    let mut out = String::new();
+    writeln!(out, ".ascii \"{}\" // Game Title", header.title).unwrap();
+    writeln!(out, ".skip {}", 12 - header.title.len()).unwrap();
+    writeln!(out, ".ascii \"{}\" // Game Code", header.game_code).unwrap();
+    writeln!(out, ".ascii \"{}\" // Maker Code", header.maker_code).unwrap();
+    writeln!(out, ".byte 0x96 // Fixed value").unwrap();
+    writeln!(out, ".byte 0 // Main unit").unwrap();
+    writeln!(out, ".byte 0 // Device type").unwrap();
+    writeln!(out, ".skip 7 // Reserved").unwrap();
+    writeln!(out, ".byte {} // Version", header.version).unwrap();
+    writeln!(out, ".byte {:#x} // Checksum", checksum).unwrap();
+    fs::write(out_dir.join("header.inc"), out).unwrap();
+}
+
 fn main() {
-    let include_path = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("c_inc");
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let manifest_dir = Path::new(&manifest_dir);
+    let include_path = manifest_dir.join("c_inc");
+    for path in &["build.rs", "asm_src", "c_inc", "third_party"] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    write_assets(manifest_dir);
+    let out_dir = env::var("OUT_DIR").unwrap();
+    write_header(&read_header(manifest_dir), Path::new(&out_dir));
 
-    cc::Build::new()
-        .file("asm_src/init.S")
+    // Host builds are only for the tests, which never reach the asm or malloc
+    if env::var("CARGO_CFG_TARGET_ARCH").unwrap() != "arm" {
+        return;
+    }
+
+    let save_types: Vec<_> = ["SRAM", "FLASH64", "FLASH128", "EEPROM"]
+        .iter()
+        .filter(|save| env::var_os(format!("CARGO_FEATURE_SAVE_{}", save)).is_some())
+        .collect();
+    if save_types.len() > 1 {
+        panic!("Only one save feature can be enabled, use --no-default-features to drop save-sram");
+    }
+
+    let mut asm = cc::Build::new();
+    asm.include(&out_dir);
+    for save in save_types {
+        asm.define(&format!("SAVE_{}", save), None);
+    }
+    if env::var_os("CARGO_FEATURE_MULTIBOOT").is_some() {
+        asm.define("MULTIBOOT", None);
+    }
+    if env::var_os("CARGO_FEATURE_RTC").is_some() {
+        asm.define("RTC", None);
+    }
+    asm.file("asm_src/init.S")
         .file("asm_src/memcpy.S")
         .file("asm_src/util.S")
         .file("asm_src/data.S")
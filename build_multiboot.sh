@@ -0,0 +1,5 @@
+#!/usr/bin/env bash
+python3 py/create_fs.py data data.bin
+CC=clang rustup run nightly xargo build --release --target thumbv4t-none-eabi --features multiboot
+arm-none-eabi-ld --whole-archive target/thumbv4t-none-eabi/release/libgba_test.a -o multiboot-gba-test.elf --gc-sections -Tgba_mb.LD
+arm-none-eabi-objcopy -O binary multiboot-gba-test.elf multiboot-gba-test.mb
@@ -1,13 +1,26 @@
 #!/usr/bin/env python3
-import sys
+import argparse
+import fnmatch
+import hashlib
 import os
 import struct
 
+# Must match the alignment of ROOT_DIR in data.S
+MAX_ALIGN = 256
+
 def align_dir(dir):
     if len(dir) % 4 != 0:
         dir += bytes(4 - (len(dir) % 4))
 
-def create_dir(path):
+def file_align(rel_path, aligns):
+    for pattern, align in aligns:
+        if fnmatch.fnmatch(rel_path, pattern):
+            return align
+    return 4
+
+# base is the offset of dir from the start of the root dir, alignment and links are relative to it.
+# payloads maps content hashes to the offset of data already in the image when deduplicating.
+def create_dir(path, rel_path, base, aligns, payloads):
     dir = bytearray()
     with os.scandir(path) as it:
         for entry in it:
@@ -19,21 +32,54 @@ def create_dir(path):
             align_dir(dir)
             if not (entry.is_file() or entry.is_dir()):
                 raise RuntimeError('Unknown object in dir')
+            entry_path = rel_path + entry.name
             is_dir = entry.is_dir()
             if is_dir:
-               data = create_dir(entry.path)
+                data = create_dir(entry.path, entry_path + '/', base + len(dir) + 4, aligns, payloads)
+                align = 4
             else:
                 with open(entry.path, mode='rb') as f:
                     data = f.read()
+                align = file_align(entry_path, aligns)
             flag_size = len(data)
             if is_dir:
                 flag_size |= 0x2000000
+            elif payloads is not None and len(data) > 4:
+                digest = hashlib.sha256(data).digest()
+                shared = payloads.get(digest)
+                if shared is not None and shared % align == 0:
+                    dir += struct.pack("<II", flag_size | 0x8000000, shared)
+                    continue
+            if align > 4:
+                flag_size |= 0x4000000
             dir += struct.pack("<I", flag_size)
+            if align > 4:
+                data_begin = base + len(dir) + 4
+                pad = (align - data_begin % align) % align
+                dir += struct.pack("<I", pad)
+                dir += bytes(pad)
+            if payloads is not None and not is_dir:
+                payloads.setdefault(hashlib.sha256(data).digest(), base + len(dir))
             dir += data
             align_dir(dir)
     dir += bytes(4)
     return dir
 
-root_dir = create_dir(sys.argv[1])
-with open(sys.argv[2], 'wb') as out:
+def parse_align(arg):
+    pattern, _, align = arg.rpartition('=')
+    align = int(align, base=0)
+    if not pattern or align < 4 or align > MAX_ALIGN or align & (align - 1) != 0:
+        raise argparse.ArgumentTypeError('Expected PATTERN=N with N a power of 2 from 4 to {0}'.format(MAX_ALIGN))
+    return (pattern, align)
+
+parser = argparse.ArgumentParser()
+parser.add_argument('dir')
+parser.add_argument('out')
+parser.add_argument('--align', action='append', default=[], type=parse_align, metavar='PATTERN=N',
+                    help='align files whose path matches PATTERN to N bytes, the first match wins')
+parser.add_argument('--no-dedup', action='store_true', help='store every file even if its contents are already in the image')
+args = parser.parse_args()
+
+root_dir = create_dir(args.dir, '', 0, args.align, None if args.no_dedup else {})
+with open(args.out, 'wb') as out:
     out.write(root_dir)
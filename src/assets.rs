@@ -0,0 +1,33 @@
+use crate::file::RomFile;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum AssetKind {
+    Image,
+    Text,
+    SoundEffect,
+    Music,
+    Sound,
+    Recording,
+    Binary,
+}
+
+// Offset is from the start of the root dir
+#[derive(Clone, Copy, Debug)]
+pub struct Asset {
+    #[allow(dead_code)]
+    pub path: &'static str,
+    pub offset: usize,
+    pub size: usize,
+    #[allow(dead_code)]
+    pub kind: AssetKind,
+}
+
+impl Asset {
+    pub fn open(&self) -> RomFile {
+        RomFile::from_root_range(self.offset, self.size)
+    }
+}
+
+// A constant for every file in data.bin, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
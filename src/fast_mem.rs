@@ -1,4 +1,6 @@
-use crate::volatile::{VolatileBool, VolatileMutPtr, VolatileUsize};
+use crate::free_list::FreeListHeap;
+use crate::lock::IrqSafeRefCell;
+use crate::volatile::VolatileBool;
 use alloc::alloc::{AllocError, Allocator, Layout};
 use alloc::boxed::Box;
 use core::arch::asm;
@@ -18,12 +20,11 @@ type FAST_HEAP_TYPE = UnsafeCell<[u32; 0x800]>;
 #[link_section = ".fast_bss"]
 static mut FAST_HEAP: FAST_HEAP_TYPE = UnsafeCell::new([0; 0x800]);
 
-#[link_section = ".fast_bss"]
-static FAST_HEAP_COUNT: VolatileUsize = VolatileUsize::new(0);
-
 #[link_section = ".fast_data"]
-static FAST_HEAP_CURRENT: VolatileMutPtr<u8> =
-    unsafe { VolatileMutPtr::new(FAST_HEAP.get() as *mut u8) };
+static FAST_HEAP_STATE: IrqSafeRefCell<FreeListHeap<'static>> =
+    IrqSafeRefCell::new(FreeListHeap::new(unsafe {
+        slice::from_raw_parts_mut(FAST_HEAP.get() as *mut u8, mem::size_of::<FAST_HEAP_TYPE>())
+    }));
 
 pub struct FastAllocator {
     _priv: PhantomData<*mut ()>,
@@ -31,11 +32,27 @@ pub struct FastAllocator {
 
 impl FastAllocator {
     pub fn new() -> Self {
-        let count = FAST_HEAP_COUNT.read();
-        assert!(count != usize::MAX);
-        FAST_HEAP_COUNT.write(count + 1);
         Self { _priv: PhantomData }
     }
+
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let (block, size) = unsafe {
+            FAST_HEAP_STATE
+                .borrow_mut()
+                .reallocate(ptr.as_ptr(), old_layout, new_layout)
+                .ok_or(AllocError)?
+        };
+        unsafe {
+            Ok(NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                block, size,
+            )))
+        }
+    }
 }
 
 impl Clone for FastAllocator {
@@ -44,39 +61,44 @@ impl Clone for FastAllocator {
     }
 }
 
-impl Drop for FastAllocator {
-    fn drop(&mut self) {
-        let count = FAST_HEAP_COUNT.read();
-        FAST_HEAP_COUNT.write(count - 1);
-        if count == 1 {
-            unsafe { FAST_HEAP_CURRENT.write(FAST_HEAP.get() as *mut u8) }
-        }
-    }
-}
-
 unsafe impl Allocator for FastAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let current = FAST_HEAP_CURRENT.read();
-        let fast_heap_loc = unsafe { FAST_HEAP.get() as usize };
-        let left = mem::size_of::<FAST_HEAP_TYPE>() - ((current as usize) - (fast_heap_loc));
-        let used = current.align_offset(layout.align()) + layout.size();
-
-        if used > left {
-            return Err(AllocError);
+        let (block, size) = FAST_HEAP_STATE
+            .borrow_mut()
+            .allocate(layout)
+            .ok_or(AllocError)?;
+        unsafe {
+            Ok(NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                block, size,
+            )))
         }
+    }
 
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         unsafe {
-            let ret = current.add(current.align_offset(layout.align()));
-            let next_current = ret.add(layout.size());
-            FAST_HEAP_CURRENT.write(next_current);
-            Ok(NonNull::new_unchecked(slice::from_raw_parts_mut(
-                ret,
-                layout.size(),
-            )))
+            FAST_HEAP_STATE
+                .borrow_mut()
+                .deallocate(ptr.as_ptr(), layout)
         }
     }
 
-    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {}
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.resize(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.resize(ptr, old_layout, new_layout) }
+    }
 }
 
 #[no_mangle]
@@ -1,5 +1,9 @@
+use crate::io::{seek_offset, BufRead, Read, ReadError, Seek, SeekError, SeekFrom};
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::cmp::min;
-use core::convert::{TryFrom, TryInto};
+use core::convert::TryInto;
+use core::mem;
 use core::slice;
 use core::str;
 
@@ -15,139 +19,358 @@ fn get_root_dir() -> &'static [u8] {
     }
 }
 
-pub struct RomFile {
+struct Member {
+    name: &'static [u8],
     data: &'static [u8],
-    offset: usize,
+    is_dir: bool,
 }
 
-#[derive(Debug)]
-pub enum OpenError {
-    NotFound,
-    IsFile,
-    IsDir,
+struct Members {
+    dir: &'static [u8],
+    index: usize,
 }
 
-pub enum SeekFrom {
-    Start(u32),
-    End(i32),
-    Current(i32),
+impl Members {
+    fn new(dir: &'static [u8]) -> Self {
+        Members { dir, index: 0 }
+    }
 }
 
-#[non_exhaustive]
-#[derive(Debug)]
-pub enum SeekError {
-    NegativeOffset,
-}
+impl Iterator for Members {
+    type Item = Member;
 
-impl RomFile {
-    pub fn raw_open<T>(name: T) -> Result<Self, OpenError>
-    where
-        T: IntoIterator<Item = u8>,
-        T::IntoIter: Clone,
-    {
-        let root_dir = get_root_dir();
-        let mut dir_index = 0;
-        let mut name_bytes = name.into_iter();
-        let mut name_lookahead = name_bytes.clone();
-        while let Some(b'/') = name_lookahead.next() {
-            assert!(name_bytes.next().is_some())
+    fn next(&mut self) -> Option<Member> {
+        let dir = self.dir;
+        let name_size = usize::from(*dir.get(self.index)?);
+        if name_size == 0 {
+            return None;
         }
+        let name = &dir[self.index + 1..self.index + 1 + name_size];
+        let mut dir_index = self.index + 1 + name_size;
+        dir_index = dir_index.next_multiple_of(4);
+        let mut size =
+            u32::from_le_bytes(dir[dir_index..dir_index + 4].try_into().unwrap()) as usize;
+        dir_index += 4;
+        let is_dir = size & 0x2000000 == 0x2000000;
+        if size & 0x4000000 == 0x4000000 {
+            let padding = u32::from_le_bytes(dir[dir_index..dir_index + 4].try_into().unwrap());
+            dir_index += 4 + padding as usize;
+        }
+        let is_link = size & 0x8000000 == 0x8000000;
+        size &= 0x1FFFFFF;
+
+        let data = if is_link {
+            let target =
+                u32::from_le_bytes(dir[dir_index..dir_index + 4].try_into().unwrap()) as usize;
+            dir_index += 4;
+            &get_root_dir()[target..target + size]
+        } else {
+            dir_index += size;
+            &dir[dir_index - size..dir_index]
+        };
+        dir_index = dir_index.next_multiple_of(4);
+        self.index = dir_index;
+        Some(Member { name, data, is_dir })
+    }
+}
+
+// A name in a path, kept as a cursor into the path so nothing has to be collected
+struct PathName<I> {
+    bytes: I,
+    len: usize,
+    last: bool, // Not even a slash after it
+}
 
-        let mut name_begin = name_bytes.clone();
+impl<I: Iterator<Item = u8> + Clone> PathName<I> {
+    fn is(&self, name: &[u8]) -> bool {
+        self.len == name.len() && self.bytes.clone().take(self.len).eq(name.iter().copied())
+    }
+}
+
+// Names in a path, `.` and empty names from doubled or trailing slashes are dropped
+struct PathNames<I> {
+    bytes: I,
+}
+
+impl<I> PathNames<I> {
+    fn new(bytes: I) -> Self {
+        PathNames { bytes }
+    }
+}
+
+impl<I: Iterator<Item = u8> + Clone> Iterator for PathNames<I> {
+    type Item = PathName<I>;
+
+    fn next(&mut self) -> Option<PathName<I>> {
         loop {
-            let mut name_size = root_dir[dir_index];
-            dir_index += 1;
-            if name_size == 0 {
-                return Err(OpenError::NotFound);
+            let len = self.bytes.clone().take_while(|byte| *byte != b'/').count();
+            let name = PathName {
+                bytes: self.bytes.clone(),
+                len,
+                last: false,
+            };
+            // Skips the name and the slash after it
+            let skipped = self.bytes.by_ref().take(len + 1).count();
+            if skipped == 0 {
+                return None;
             }
-            let mut this_entry = true;
-            while name_size > 0 {
-                let char = if let Some(c) = name_bytes.next() {
-                    c
-                } else {
-                    break;
-                };
-                if char != root_dir[dir_index] {
-                    this_entry = false;
-                    break;
-                }
-                dir_index += 1;
-                name_size -= 1;
+            if len != 0 && !name.is(b".") {
+                return Some(PathName {
+                    last: skipped == len,
+                    ..name
+                });
             }
-            this_entry = this_entry && name_size == 0;
-            dir_index += usize::from(name_size);
-            if dir_index % 4 != 0 {
-                dir_index += 4 - (dir_index % 4)
+        }
+    }
+}
+
+// The path from the root with `.`, `..` and doubled slashes resolved, so every spelling of a path
+// gives the same string
+pub(crate) fn normalize_path(path: &str) -> String {
+    let mut normalized = Vec::new();
+    for name in PathNames::new(path.bytes()) {
+        if name.is(b"..") {
+            let parent = normalized.iter().rposition(|byte| *byte == b'/');
+            normalized.truncate(parent.unwrap_or(0));
+        } else {
+            if !normalized.is_empty() {
+                normalized.push(b'/');
             }
-            let mut size =
-                u32::from_le_bytes(root_dir[dir_index..dir_index + 4].try_into().unwrap()) as usize;
-            dir_index += 4;
-            let is_dir = size & 0x2000000 == 0x2000000;
-            size &= 0x1FFFFFF;
-            if this_entry {
-                let next_byte = name_bytes.next();
-                if next_byte.is_none() && is_dir {
-                    return Err(OpenError::IsDir);
-                } else if next_byte == Some(b'/') && !is_dir {
-                    return Err(OpenError::IsFile);
-                } else if next_byte.is_some() && next_byte != Some(b'/') {
-                    this_entry = false;
-                }
+            normalized.extend(name.bytes.take(name.len));
+        }
+    }
+    // Names are only split at ascii slashes, so it's still utf8
+    String::from_utf8(normalized).unwrap()
+}
+
+// Deeper dirs can't be opened, create_fs.py doesn't make any
+const MAX_DEPTH: usize = 16;
+
+// Only ever returned from walk and matched right away, so the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum Node {
+    Dir(RomDir),
+    File(&'static [u8]),
+}
+
+// A dir in the rom filesystem that paths can be opened relative to. It keeps the dirs above it
+// so `..` doesn't need another walk from the root.
+#[derive(Clone, Copy)]
+pub struct RomDir {
+    dirs: [&'static [u8]; MAX_DEPTH], // Root first
+    depth: usize,
+}
+
+impl RomDir {
+    pub fn root() -> Self {
+        RomDir {
+            dirs: [get_root_dir(); MAX_DEPTH],
+            depth: 1,
+        }
+    }
+
+    // Paths starting with / are from the root, `..` at the root stays there
+    fn walk<I>(&self, path: I) -> Result<Node, OpenError>
+    where
+        I: Iterator<Item = u8> + Clone,
+    {
+        let mut dir = *self;
+        if path.clone().next() == Some(b'/') {
+            dir.depth = 1;
+        }
+        for name in PathNames::new(path) {
+            if name.is(b"..") {
+                dir = dir.parent();
+                continue;
             }
-            if this_entry {
-                if is_dir {
-                    name_begin = name_bytes.clone();
-                } else {
-                    return Ok(Self {
-                        data: &root_dir[dir_index..dir_index + size],
-                        offset: 0,
-                    });
+            let member = Members::new(dir.dirs[dir.depth - 1])
+                .find(|member| name.is(member.name))
+                .ok_or(OpenError::NotFound)?;
+            if member.is_dir {
+                if dir.depth == MAX_DEPTH {
+                    return Err(OpenError::TooDeep);
                 }
+                dir.dirs[dir.depth] = member.data;
+                dir.depth += 1;
+            } else if name.last {
+                return Ok(Node::File(member.data));
             } else {
-                name_bytes = name_begin.clone();
-                dir_index += size;
+                return Err(OpenError::IsFile);
             }
+        }
+        Ok(Node::Dir(dir))
+    }
 
-            if dir_index % 4 != 0 {
-                dir_index += 4 - (dir_index % 4)
-            }
+    pub fn raw_open(&self, path: &[u8]) -> Result<RomFile, OpenError> {
+        match self.walk(path.iter().copied())? {
+            Node::File(data) => Ok(RomFile { data, offset: 0 }),
+            Node::Dir(_) => Err(OpenError::IsDir),
         }
     }
 
-    pub fn open(name: &str) -> Result<RomFile, OpenError> {
-        Self::raw_open(name.bytes())
+    pub fn open(&self, path: &str) -> Result<RomFile, OpenError> {
+        self.raw_open(path.as_bytes())
     }
 
-    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
-        let data_end = self.data.len().saturating_sub(self.offset);
-        let read_end = min(data_end, buffer.len());
-        buffer[..read_end].copy_from_slice(&self.data[self.offset..self.offset + read_end]);
-        self.offset += read_end;
-        read_end
+    #[allow(dead_code)]
+    pub fn open_dir(&self, path: &str) -> Result<RomDir, OpenError> {
+        match self.walk(path.bytes())? {
+            Node::Dir(dir) => Ok(dir),
+            Node::File(_) => Err(OpenError::IsFile),
+        }
     }
 
-    pub fn seek(&mut self, pos: SeekFrom) -> Result<u32, SeekError> {
-        let new_offset = match pos {
-            SeekFrom::Start(offset) => Some(offset),
-            SeekFrom::End(offset) => {
-                let size = self.data.len() as i32;
-                size.checked_add(offset).and_then(|x| u32::try_from(x).ok())
-            }
-            SeekFrom::Current(offset) => (self.offset as i32)
-                .checked_add(offset)
-                .and_then(|x| u32::try_from(x).ok()),
-        };
-        if let Some(off) = new_offset {
-            self.offset = off as usize
+    pub fn parent(&self) -> RomDir {
+        RomDir {
+            depth: (self.depth - 1).max(1),
+            ..*self
+        }
+    }
+}
+
+pub struct RomFile {
+    data: &'static [u8],
+    offset: usize,
+}
+
+#[derive(Debug)]
+pub enum CastError {
+    Misaligned,
+    BadSize,
+}
+
+/// # Safety
+/// Every bit pattern of the type's size must be a valid value and it can't contain padding.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for i64 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+#[derive(Debug)]
+pub enum OpenError {
+    NotFound,
+    IsFile,
+    IsDir,
+    TooDeep,
+}
+
+impl RomFile {
+    #[allow(dead_code)]
+    pub fn raw_open<T>(name: T) -> Result<Self, OpenError>
+    where
+        T: IntoIterator<Item = u8>,
+        T::IntoIter: Clone,
+    {
+        match RomDir::root().walk(name.into_iter())? {
+            Node::File(data) => Ok(RomFile { data, offset: 0 }),
+            Node::Dir(_) => Err(OpenError::IsDir),
+        }
+    }
+
+    pub fn open(name: &str) -> Result<RomFile, OpenError> {
+        RomDir::root().open(name)
+    }
+
+    pub(crate) fn from_root_range(offset: usize, size: usize) -> RomFile {
+        RomFile {
+            data: &get_root_dir()[offset..offset + size],
+            offset: 0,
         }
-        new_offset.ok_or(SeekError::NegativeOffset)
     }
 
     pub fn as_bytes(&self) -> &'static [u8] {
         &self.data[self.offset..]
     }
 
+    // Files are only 4 byte aligned unless create_fs.py was given --align for them
+    #[allow(dead_code)]
+    pub fn as_slice_of<T: Pod>(&self) -> Result<&'static [T], CastError> {
+        let bytes = self.as_bytes();
+        if !(bytes.as_ptr() as usize).is_multiple_of(mem::align_of::<T>()) {
+            return Err(CastError::Misaligned);
+        }
+        if mem::size_of::<T>() == 0 || !bytes.len().is_multiple_of(mem::size_of::<T>()) {
+            return Err(CastError::BadSize);
+        }
+        let len = bytes.len() / mem::size_of::<T>();
+        unsafe { Ok(slice::from_raw_parts(bytes.as_ptr() as *const T, len)) }
+    }
+
     pub fn as_str(&self) -> Result<&'static str, str::Utf8Error> {
         str::from_utf8(self.as_bytes())
     }
 }
+
+impl Read for RomFile {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        let read_end = min(self.data.len() - self.offset, buffer.len());
+        buffer[..read_end].copy_from_slice(&self.data[self.offset..self.offset + read_end]);
+        self.offset += read_end;
+        Ok(read_end)
+    }
+}
+
+impl BufRead for RomFile {
+    fn fill_buf(&mut self) -> Result<&[u8], ReadError> {
+        Ok(&self.data[self.offset..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.offset = min(self.offset + amount, self.data.len());
+    }
+}
+
+impl Seek for RomFile {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u32, SeekError> {
+        self.offset = seek_offset(self.data.len(), self.offset, pos)?;
+        Ok(self.offset as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(path: &str) -> Vec<(Vec<u8>, bool)> {
+        PathNames::new(path.bytes())
+            .map(|name| (name.bytes.clone().take(name.len).collect(), name.last))
+            .collect()
+    }
+
+    #[test]
+    fn path_names() {
+        assert_eq!(
+            names("a/bc"),
+            [(b"a".to_vec(), false), (b"bc".to_vec(), true)]
+        );
+        assert_eq!(
+            names("//a/./../b/"),
+            [
+                (b"a".to_vec(), false),
+                (b"..".to_vec(), false),
+                (b"b".to_vec(), false)
+            ]
+        );
+        assert_eq!(names("a/."), [(b"a".to_vec(), false)]);
+        assert!(names("").is_empty());
+        assert!(names("/./").is_empty());
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_path("save.bin"), "save.bin");
+        assert_eq!(normalize_path("/dir//save.bin"), "dir/save.bin");
+        assert_eq!(normalize_path("./dir/./save.bin/"), "dir/save.bin");
+        assert_eq!(normalize_path("dir/sub/../../save.bin"), "save.bin");
+        assert_eq!(normalize_path("../../save.bin"), "save.bin");
+        assert_eq!(normalize_path("dir/ü/.."), "dir");
+        assert_eq!(normalize_path("/"), "");
+    }
+}
@@ -0,0 +1,252 @@
+use alloc::alloc::Layout;
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr;
+
+const MIN_CLASS_SHIFT: u32 = 3;
+const CLASSES: usize = 11; // 8 bytes up to 8K, the size of the fast heap
+
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+// Blocks are rounded up to a power of two and freed ones go on the list for their size, so
+// allocating is either a list pop or a bump of `current`. Freeing the last live block resets the
+// whole arena, which undoes any fragmentation.
+pub struct FreeListHeap<'a> {
+    free: [*mut FreeBlock; CLASSES],
+    start: *mut u8,
+    end: *mut u8,
+    current: *mut u8,
+    live: usize,
+    _arena: PhantomData<&'a mut [u8]>,
+}
+
+unsafe impl<'a> Send for FreeListHeap<'a> {}
+
+fn class_of(layout: Layout) -> Option<usize> {
+    let size = layout
+        .size()
+        .max(layout.align())
+        .max(1 << MIN_CLASS_SHIFT)
+        .next_power_of_two();
+    let class = (size.trailing_zeros() - MIN_CLASS_SHIFT) as usize;
+    if class < CLASSES {
+        Some(class)
+    } else {
+        None
+    }
+}
+
+fn class_size(class: usize) -> usize {
+    1 << (class as u32 + MIN_CLASS_SHIFT)
+}
+
+impl<'a> FreeListHeap<'a> {
+    pub const fn new(arena: &'a mut [u8]) -> Self {
+        let start = arena.as_mut_ptr();
+        FreeListHeap {
+            free: [ptr::null_mut(); CLASSES],
+            start,
+            end: unsafe { start.add(arena.len()) },
+            current: start,
+            live: 0,
+            _arena: PhantomData,
+        }
+    }
+
+    // Returns the block and its rounded up size
+    pub fn allocate(&mut self, layout: Layout) -> Option<(*mut u8, usize)> {
+        let class = class_of(layout)?;
+        let size = class_size(class);
+        let head = self.free[class];
+        let block = if !head.is_null() && (head as usize).is_multiple_of(layout.align()) {
+            self.free[class] = unsafe { (*head).next };
+            head as *mut u8
+        } else {
+            let offset = self
+                .current
+                .align_offset(layout.align().max(mem::align_of::<FreeBlock>()));
+            if (self.end as usize - self.current as usize) < offset + size {
+                return None;
+            }
+            unsafe {
+                let block = self.current.add(offset);
+                self.current = block.add(size);
+                block
+            }
+        };
+        self.live += 1;
+        Some((block, size))
+    }
+
+    /// # Safety
+    /// `block` must come from `allocate` on this heap with the same layout.
+    pub unsafe fn deallocate(&mut self, block: *mut u8, layout: Layout) {
+        let class = class_of(layout).unwrap();
+        self.live -= 1;
+        if self.live == 0 {
+            self.free = [ptr::null_mut(); CLASSES];
+            self.current = self.start;
+        } else if unsafe { block.add(class_size(class)) } == self.current {
+            self.current = block; // The last block bumped goes straight back
+        } else {
+            let block = block as *mut FreeBlock;
+            unsafe { (*block).next = self.free[class] };
+            self.free[class] = block;
+        }
+    }
+
+    /// # Safety
+    /// `block` must come from `allocate` on this heap with `old_layout`.
+    pub unsafe fn reallocate(
+        &mut self,
+        block: *mut u8,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<(*mut u8, usize)> {
+        // Stays in place when both layouts round to the same block size
+        let class = class_of(new_layout)?;
+        if Some(class) == class_of(old_layout)
+            && (block as usize).is_multiple_of(new_layout.align())
+        {
+            return Some((block, class_size(class)));
+        }
+
+        let (new, size) = self.allocate(new_layout)?;
+        unsafe {
+            ptr::copy_nonoverlapping(block, new, old_layout.size().min(new_layout.size()));
+            self.deallocate(block, old_layout);
+        }
+        Some((new, size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(align(64))]
+    struct Arena([u8; 256]);
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    // Offset of a new block from the start of the arena
+    fn allocate(heap: &mut FreeListHeap, size: usize, align: usize) -> Option<(usize, usize)> {
+        let (block, size) = heap.allocate(layout(size, align))?;
+        Some((block as usize - heap.start as usize, size))
+    }
+
+    fn deallocate(heap: &mut FreeListHeap, offset: usize, size: usize, align: usize) {
+        unsafe { heap.deallocate(heap.start.add(offset), layout(size, align)) }
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(class_of(layout(0, 1)), Some(0));
+        assert_eq!(class_of(layout(8, 8)), Some(0));
+        assert_eq!(class_of(layout(9, 1)), Some(1));
+        assert_eq!(class_of(layout(4, 16)), Some(1));
+        assert_eq!(class_of(layout(0x2000, 4)), Some(CLASSES - 1));
+        assert_eq!(class_of(layout(0x2001, 4)), None);
+
+        let mut arena = Arena([0; 256]);
+        let mut heap = FreeListHeap::new(&mut arena.0);
+        assert_eq!(allocate(&mut heap, 1, 1), Some((0, 8)));
+        assert_eq!(allocate(&mut heap, 20, 4), Some((8, 32)));
+        assert_eq!(allocate(&mut heap, 2, 32), Some((64, 32)));
+    }
+
+    #[test]
+    fn free_lists() {
+        let mut arena = Arena([0; 256]);
+        let mut heap = FreeListHeap::new(&mut arena.0);
+        assert_eq!(allocate(&mut heap, 8, 8), Some((0, 8)));
+        assert_eq!(allocate(&mut heap, 16, 8), Some((8, 16)));
+        assert_eq!(allocate(&mut heap, 8, 8), Some((24, 8)));
+        deallocate(&mut heap, 8, 16, 8);
+
+        // The freed block isn't aligned enough, so a new one is bumped with padding before it
+        assert_eq!(allocate(&mut heap, 8, 8), Some((32, 8)));
+        assert_eq!(allocate(&mut heap, 16, 16), Some((48, 16)));
+        assert_eq!(allocate(&mut heap, 12, 4), Some((8, 16)));
+        assert_eq!(allocate(&mut heap, 16, 8), Some((64, 16)));
+
+        // The last block bumped is given back to `current` instead of the free list
+        deallocate(&mut heap, 64, 16, 8);
+        assert_eq!(allocate(&mut heap, 8, 8), Some((64, 8)));
+        assert_eq!(heap.live, 6);
+    }
+
+    #[test]
+    fn reset() {
+        let mut arena = Arena([0; 256]);
+        let mut heap = FreeListHeap::new(&mut arena.0);
+        assert_eq!(allocate(&mut heap, 16, 8), Some((0, 16)));
+        assert_eq!(allocate(&mut heap, 16, 8), Some((16, 16)));
+        assert_eq!(allocate(&mut heap, 8, 8), Some((32, 8)));
+        deallocate(&mut heap, 0, 16, 8);
+        deallocate(&mut heap, 16, 16, 8);
+        assert!(!heap.free[1].is_null());
+
+        // Freeing the last live block empties the free lists along with it
+        deallocate(&mut heap, 32, 8, 8);
+        assert_eq!(heap.live, 0);
+        assert!(heap.free.iter().all(|block| block.is_null()));
+        assert_eq!(allocate(&mut heap, 64, 8), Some((0, 64)));
+    }
+
+    #[test]
+    fn out_of_space() {
+        let mut arena = Arena([0; 256]);
+        let mut heap = FreeListHeap::new(&mut arena.0[..64]);
+        assert_eq!(allocate(&mut heap, 128, 8), None);
+        assert_eq!(allocate(&mut heap, 8, 8), Some((0, 8)));
+        assert_eq!(allocate(&mut heap, 64, 8), None);
+        assert_eq!(allocate(&mut heap, 32, 32), Some((32, 32)));
+
+        // Padding isn't handed out again until the heap resets
+        assert_eq!(allocate(&mut heap, 8, 8), None);
+        assert_eq!(heap.live, 2);
+    }
+
+    #[test]
+    fn reallocate() {
+        let mut arena = Arena([0; 256]);
+        let mut heap = FreeListHeap::new(&mut arena.0);
+        let (block, _) = heap.allocate(layout(9, 1)).unwrap();
+        let (pin, _) = heap.allocate(layout(8, 8)).unwrap();
+        unsafe {
+            ptr::copy_nonoverlapping(b"123456789".as_ptr(), block, 9);
+
+            // 9 and 16 bytes are both in the 16 byte class
+            let same = heap.reallocate(block, layout(9, 1), layout(16, 1));
+            assert_eq!(same, Some((block, 16)));
+
+            let (grown, size) = heap
+                .reallocate(block, layout(16, 1), layout(40, 1))
+                .unwrap();
+            assert_ne!(grown, block);
+            assert_eq!(size, 64);
+            assert_eq!(&*ptr::slice_from_raw_parts(grown, 9), b"123456789");
+            assert!(!heap.free[1].is_null());
+
+            let (shrunk, size) = heap.reallocate(grown, layout(40, 1), layout(4, 1)).unwrap();
+            assert_ne!(shrunk, grown);
+            assert_eq!(size, 8);
+            assert_eq!(&*ptr::slice_from_raw_parts(shrunk, 4), b"1234");
+
+            assert_eq!(
+                heap.reallocate(shrunk, layout(4, 1), layout(0x4000, 1)),
+                None
+            );
+            assert_eq!(heap.live, 2);
+            heap.deallocate(shrunk, layout(4, 1));
+            heap.deallocate(pin, layout(8, 8));
+        }
+        assert_eq!(heap.live, 0);
+        assert_eq!(heap.current, heap.start);
+    }
+}
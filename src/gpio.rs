@@ -0,0 +1,41 @@
+use core::marker::PhantomData;
+use core::ptr;
+
+// The 4 bit port some carts wire extra hardware like a real time clock to. It sits on top of
+// rom, so the pins only read back while reads are enabled.
+pub struct Gpio {
+    _priv: PhantomData<*mut ()>,
+}
+
+#[allow(dead_code)]
+impl Gpio {
+    pub fn new() -> Self {
+        unsafe { ptr::write_volatile(0x80000C8 as *mut u16, 1) }; // Enable reads
+        Gpio { _priv: PhantomData }
+    }
+
+    // Set bits are outputs
+    pub fn set_direction(&mut self, outputs: u8) {
+        unsafe { ptr::write_volatile(0x80000C6 as *mut u16, u16::from(outputs & 0xF)) };
+    }
+
+    pub fn write(&mut self, value: u8) {
+        unsafe { ptr::write_volatile(0x80000C4 as *mut u16, u16::from(value & 0xF)) };
+    }
+
+    pub fn read(&self) -> u8 {
+        unsafe { ptr::read_volatile(0x80000C4 as *const u16) as u8 & 0xF }
+    }
+}
+
+impl Default for Gpio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Gpio {
+    fn drop(&mut self) {
+        unsafe { ptr::write_volatile(0x80000C8 as *mut u16, 0) }; // Back to reading as rom
+    }
+}
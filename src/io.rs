@@ -0,0 +1,257 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::convert::TryFrom;
+use core::str;
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ReadError {
+    UnexpectedEof,
+    InvalidUtf8,
+}
+
+pub enum SeekFrom {
+    Start(u32),
+    #[allow(dead_code)]
+    End(i32),
+    #[allow(dead_code)]
+    Current(i32),
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum SeekError {
+    NegativeOffset,
+    PastEnd,
+}
+
+#[allow(dead_code)]
+pub trait Read {
+    // Returns 0 only at the end of the data or for an empty buffer
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ReadError>;
+
+    fn read_exact(&mut self, mut buffer: &mut [u8]) -> Result<(), ReadError> {
+        while !buffer.is_empty() {
+            match self.read(buffer)? {
+                0 => return Err(ReadError::UnexpectedEof),
+                count => buffer = &mut buffer[count..],
+            }
+        }
+        Ok(())
+    }
+
+    fn read_to_end(&mut self, out: &mut Vec<u8>) -> Result<usize, ReadError> {
+        let begin = out.len();
+        let mut chunk = [0; 256];
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(out.len() - begin),
+                count => out.extend_from_slice(&chunk[..count]),
+            }
+        }
+    }
+}
+
+pub trait Seek {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u32, SeekError>;
+
+    #[allow(dead_code)]
+    fn rewind(&mut self) -> Result<(), SeekError> {
+        self.seek(SeekFrom::Start(0)).map(|_| ())
+    }
+
+    #[allow(dead_code)]
+    fn stream_position(&mut self) -> Result<u32, SeekError> {
+        self.seek(SeekFrom::Current(0))
+    }
+}
+
+#[allow(dead_code)]
+pub trait BufRead: Read {
+    fn fill_buf(&mut self) -> Result<&[u8], ReadError>;
+
+    fn consume(&mut self, amount: usize);
+
+    // Reads up to and including `byte`
+    fn read_until(&mut self, byte: u8, out: &mut Vec<u8>) -> Result<usize, ReadError> {
+        let begin = out.len();
+        loop {
+            let buffer = self.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            let (used, done) = match buffer.iter().position(|b| *b == byte) {
+                Some(index) => (index + 1, true),
+                None => (buffer.len(), false),
+            };
+            out.extend_from_slice(&buffer[..used]);
+            self.consume(used);
+            if done {
+                break;
+            }
+        }
+        Ok(out.len() - begin)
+    }
+
+    // Appends a line including its newline, returns 0 at the end of the data
+    fn read_line(&mut self, out: &mut String) -> Result<usize, ReadError> {
+        let mut line = Vec::new();
+        let count = self.read_until(b'\n', &mut line)?;
+        out.push_str(str::from_utf8(&line).map_err(|_| ReadError::InvalidUtf8)?);
+        Ok(count)
+    }
+
+    fn lines(self) -> Lines<Self>
+    where
+        Self: Sized,
+    {
+        Lines { reader: self }
+    }
+}
+
+pub struct Lines<B> {
+    reader: B,
+}
+
+impl<B: BufRead> Iterator for Lines<B> {
+    type Item = Result<String, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Some(Ok(line))
+            }
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+// Offset `pos` points to in data of length `len`
+pub(crate) fn seek_offset(len: usize, current: usize, pos: SeekFrom) -> Result<usize, SeekError> {
+    let new_offset = match pos {
+        SeekFrom::Start(offset) => i64::from(offset),
+        SeekFrom::End(offset) => len as i64 + i64::from(offset),
+        SeekFrom::Current(offset) => current as i64 + i64::from(offset),
+    };
+    let new_offset = usize::try_from(new_offset).map_err(|_| SeekError::NegativeOffset)?;
+    if new_offset > len {
+        return Err(SeekError::PastEnd);
+    }
+    Ok(new_offset)
+}
+
+impl Read for &[u8] {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        let count = min(self.len(), buffer.len());
+        buffer[..count].copy_from_slice(&self[..count]);
+        *self = &self[count..];
+        Ok(count)
+    }
+}
+
+impl BufRead for &[u8] {
+    fn fill_buf(&mut self) -> Result<&[u8], ReadError> {
+        Ok(self)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        *self = &self[amount..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_slices() {
+        let mut data: &[u8] = b"abcdef";
+        let mut buffer = [0; 4];
+        assert_eq!(data.read(&mut buffer).unwrap(), 4);
+        assert_eq!(&buffer, b"abcd");
+        assert_eq!(data.read(&mut buffer).unwrap(), 2);
+        assert_eq!(&buffer[..2], b"ef");
+        assert_eq!(data.read(&mut buffer).unwrap(), 0);
+        assert_eq!(data.read(&mut []).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_exact() {
+        let mut data: &[u8] = b"abcdef";
+        let mut buffer = [0; 4];
+        data.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"abcd");
+        assert!(matches!(
+            data.read_exact(&mut buffer),
+            Err(ReadError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn read_to_end() {
+        let long: Vec<u8> = (0..=255).cycle().take(600).collect();
+        let mut data = &long[..];
+        let mut out = b"x".to_vec();
+        assert_eq!(data.read_to_end(&mut out).unwrap(), 600);
+        assert_eq!(out[0], b'x');
+        assert_eq!(&out[1..], &long[..]);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn read_until() {
+        let mut data: &[u8] = b"a,bc,";
+        let mut out = Vec::new();
+        assert_eq!(data.read_until(b',', &mut out).unwrap(), 2);
+        assert_eq!(data.read_until(b',', &mut out).unwrap(), 3);
+        assert_eq!(data.read_until(b',', &mut out).unwrap(), 0);
+        assert_eq!(&out, b"a,bc,");
+    }
+
+    #[test]
+    fn lines() {
+        let data: &[u8] = b"one\r\ntwo\n\nthree";
+        let lines: Vec<String> = data.lines().map(Result::unwrap).collect();
+        assert_eq!(lines, ["one", "two", "", "three"]);
+
+        let mut data: &[u8] = b"line\n";
+        let mut line = String::from(">");
+        assert_eq!(data.read_line(&mut line).unwrap(), 5);
+        assert_eq!(line, ">line\n");
+        assert_eq!(data.read_line(&mut line).unwrap(), 0);
+
+        let bad: &[u8] = b"\xFF\n";
+        assert!(matches!(
+            bad.lines().next(),
+            Some(Err(ReadError::InvalidUtf8))
+        ));
+    }
+
+    #[test]
+    fn seek_offsets() {
+        assert_eq!(seek_offset(10, 4, SeekFrom::Start(10)).unwrap(), 10);
+        assert_eq!(seek_offset(10, 4, SeekFrom::End(-3)).unwrap(), 7);
+        assert_eq!(seek_offset(10, 4, SeekFrom::Current(-4)).unwrap(), 0);
+        assert!(matches!(
+            seek_offset(10, 4, SeekFrom::Start(11)),
+            Err(SeekError::PastEnd)
+        ));
+        assert!(matches!(
+            seek_offset(10, 4, SeekFrom::End(1)),
+            Err(SeekError::PastEnd)
+        ));
+        assert!(matches!(
+            seek_offset(10, 4, SeekFrom::Current(-5)),
+            Err(SeekError::NegativeOffset)
+        ));
+    }
+}
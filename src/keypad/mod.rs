@@ -0,0 +1,297 @@
+use crate::volatile::VolatileBool;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use core::ptr;
+
+mod record;
+#[allow(unused_imports)]
+pub use record::{Playback, PlaybackError, Recorder};
+
+bitflags! {
+    pub struct Keys: u16 {
+        const A = 1 << 0;
+        const B = 1 << 1;
+        const SELECT = 1 << 2;
+        const START = 1 << 3;
+        const RIGHT = 1 << 4;
+        const LEFT = 1 << 5;
+        const UP = 1 << 6;
+        const DOWN = 1 << 7;
+        const R = 1 << 8;
+        const L = 1 << 9;
+    }
+}
+
+#[no_mangle]
+static KEYPAD_COMBO_HIT: VolatileBool = VolatileBool::new(false);
+
+#[no_mangle]
+static KEYPAD_COMBO_RESET: VolatileBool = VolatileBool::new(false);
+
+pub fn read_keyinput() -> Keys {
+    let raw = unsafe { ptr::read_volatile(0x4000130 as *const u16) };
+    Keys::from_bits_truncate(!raw) // Keys are active low
+}
+
+pub struct Keypad {
+    held: Keys,
+    previous: Keys,
+    repeated: Keys,
+    repeat_delay: u8,
+    repeat_rate: u8,
+    repeat_timer: u8,
+    playback: Option<Playback>,
+    recorder: Option<Recorder>,
+}
+
+#[allow(dead_code)]
+impl Keypad {
+    pub const fn new() -> Self {
+        Keypad {
+            held: Keys::empty(),
+            previous: Keys::empty(),
+            repeated: Keys::empty(),
+            repeat_delay: 0,
+            repeat_rate: 0,
+            repeat_timer: 0,
+            playback: None,
+            recorder: None,
+        }
+    }
+
+    // Should be called once per frame
+    pub fn poll(&mut self) {
+        let played = self.playback.as_mut().and_then(|playback| playback.next());
+        let keys = match played {
+            Some(keys) => keys,
+            None => {
+                self.playback = None;
+                read_keyinput()
+            }
+        };
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(keys)
+        }
+        self.update(keys)
+    }
+
+    // Replaces KEYINPUT until the recording runs out
+    pub fn start_playback(&mut self, playback: Playback) {
+        self.playback = Some(playback);
+    }
+
+    pub fn stop_playback(&mut self) -> Option<Playback> {
+        self.playback.take()
+    }
+
+    pub fn is_playing_back(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn start_recording(&mut self) {
+        self.recorder = Some(Recorder::new());
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        self.recorder.take().map(Recorder::finish)
+    }
+
+    pub fn update(&mut self, keys: Keys) {
+        self.previous = self.held;
+        self.held = keys;
+
+        let pressed = self.pressed();
+        if !pressed.is_empty() {
+            self.repeated = pressed;
+            self.repeat_timer = self.repeat_delay;
+        } else if self.held.is_empty() || self.repeat_delay == 0 {
+            self.repeated = Keys::empty();
+        } else {
+            self.repeat_timer = self.repeat_timer.saturating_sub(1);
+            if self.repeat_timer == 0 {
+                self.repeated = self.held;
+                self.repeat_timer = self.repeat_rate.max(1);
+            } else {
+                self.repeated = Keys::empty();
+            }
+        }
+    }
+
+    pub fn held(&self) -> Keys {
+        self.held
+    }
+
+    pub fn pressed(&self) -> Keys {
+        self.held & !self.previous
+    }
+
+    pub fn released(&self) -> Keys {
+        self.previous & !self.held
+    }
+
+    // Pressed keys plus a pulse every `rate` frames once a key is held for `delay` frames
+    pub fn repeated(&self) -> Keys {
+        self.repeated
+    }
+
+    // A delay of 0 disables auto repeat
+    pub fn set_repeat(&mut self, delay: u8, rate: u8) {
+        self.repeat_delay = delay;
+        self.repeat_rate = rate;
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComboAction {
+    #[allow(dead_code)]
+    Flag,
+    SoftReset,
+}
+
+// Raises the keypad irq once every key in `keys` is held. A flag combo only fires once, arm it
+// again to catch the next one.
+pub fn arm_combo(keys: Keys, action: ComboAction) {
+    KEYPAD_COMBO_HIT.write(false);
+    KEYPAD_COMBO_RESET.write(action == ComboAction::SoftReset);
+    unsafe {
+        ptr::write_volatile(0x4000132 as *mut u16, keys.bits() | 0xC000); // Irq on all keys
+        let enabled = ptr::read_volatile(0x4000200 as *const u16);
+        ptr::write_volatile(0x4000200 as *mut u16, enabled | 0x1000); // Turn on keypad irq
+    }
+}
+
+#[allow(dead_code)]
+pub fn disarm_combo() {
+    unsafe {
+        let enabled = ptr::read_volatile(0x4000200 as *const u16);
+        ptr::write_volatile(0x4000200 as *mut u16, enabled & !0x1000);
+        ptr::write_volatile(0x4000132 as *mut u16, 0);
+    }
+    KEYPAD_COMBO_HIT.write(false);
+}
+
+#[allow(dead_code)]
+pub fn combo_triggered() -> bool {
+    KEYPAD_COMBO_HIT.swap(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges() {
+        let mut keypad = Keypad::new();
+        keypad.update(Keys::A);
+        assert_eq!(keypad.held(), Keys::A);
+        assert_eq!(keypad.pressed(), Keys::A);
+        assert_eq!(keypad.released(), Keys::empty());
+
+        keypad.update(Keys::A | Keys::B);
+        assert_eq!(keypad.pressed(), Keys::B);
+        assert_eq!(keypad.released(), Keys::empty());
+
+        keypad.update(Keys::B);
+        assert_eq!(keypad.pressed(), Keys::empty());
+        assert_eq!(keypad.released(), Keys::A);
+
+        keypad.update(Keys::empty());
+        assert_eq!(keypad.held(), Keys::empty());
+        assert_eq!(keypad.released(), Keys::B);
+
+        keypad.update(Keys::empty());
+        assert_eq!(keypad.released(), Keys::empty());
+    }
+
+    #[test]
+    fn repeat_timing() {
+        let mut keypad = Keypad::new();
+        keypad.set_repeat(3, 2);
+        let mut repeats = Vec::new();
+        for _ in 0..9 {
+            keypad.update(Keys::UP);
+            repeats.push(keypad.repeated() == Keys::UP);
+        }
+        // The press itself, then the delay, then every `rate` frames
+        assert_eq!(
+            repeats,
+            [true, false, false, true, false, true, false, true, false]
+        );
+
+        keypad.update(Keys::empty());
+        assert_eq!(keypad.repeated(), Keys::empty());
+    }
+
+    #[test]
+    fn press_restarts_delay() {
+        let mut keypad = Keypad::new();
+        keypad.set_repeat(2, 1);
+        keypad.update(Keys::LEFT);
+        keypad.update(Keys::LEFT);
+        keypad.update(Keys::LEFT | Keys::A);
+        assert_eq!(keypad.repeated(), Keys::A);
+        keypad.update(Keys::LEFT | Keys::A);
+        assert_eq!(keypad.repeated(), Keys::empty());
+        keypad.update(Keys::LEFT | Keys::A);
+        assert_eq!(keypad.repeated(), Keys::LEFT | Keys::A);
+        keypad.update(Keys::LEFT | Keys::A);
+        assert_eq!(keypad.repeated(), Keys::LEFT | Keys::A);
+    }
+
+    #[test]
+    fn no_repeat() {
+        let mut keypad = Keypad::new();
+        keypad.update(Keys::R);
+        assert_eq!(keypad.repeated(), Keys::R);
+        for _ in 0..300 {
+            keypad.update(Keys::R);
+            assert_eq!(keypad.repeated(), Keys::empty());
+        }
+
+        // A rate of 0 repeats every frame like a rate of 1
+        keypad.set_repeat(1, 0);
+        keypad.update(Keys::empty());
+        keypad.update(Keys::R);
+        for _ in 0..3 {
+            keypad.update(Keys::R);
+            assert_eq!(keypad.repeated(), Keys::R);
+        }
+    }
+
+    #[test]
+    fn playback_is_recorded() {
+        let frames = [Keys::empty(), Keys::START, Keys::START, Keys::empty()];
+        let mut recorder = Recorder::new();
+        for keys in frames.iter() {
+            recorder.record(*keys);
+        }
+        // One extra frame so poll never falls back to KEYINPUT
+        recorder.record(Keys::empty());
+        let playback = Playback::from_recording(recorder.finish()).unwrap();
+
+        let mut keypad = Keypad::new();
+        keypad.start_playback(playback);
+        keypad.start_recording();
+        for keys in frames.iter() {
+            keypad.poll();
+            assert_eq!(keypad.held(), *keys);
+        }
+        assert!(keypad.is_playing_back());
+        assert_eq!(keypad.released(), Keys::START);
+
+        let recording = keypad.stop_recording().unwrap();
+        let mut replay = Playback::from_recording(recording).unwrap();
+        for keys in frames.iter() {
+            assert_eq!(replay.next(), Some(*keys));
+        }
+        assert_eq!(replay.next(), None);
+        assert!(keypad.stop_playback().is_some());
+        assert!(!keypad.is_playing_back());
+    }
+}
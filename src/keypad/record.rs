@@ -0,0 +1,183 @@
+use super::Keys;
+use crate::file::RomFile;
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+// A recording is the magic followed by (u16 keys, u16 frame count) runs, all little endian
+const RECORDING_MAGIC: &[u8; 4] = b"KREC";
+
+pub struct Recorder {
+    data: Vec<u8>,
+    keys: Keys,
+    run: u16,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder {
+            data: RECORDING_MAGIC.to_vec(),
+            keys: Keys::empty(),
+            run: 0,
+        }
+    }
+
+    pub fn record(&mut self, keys: Keys) {
+        if self.run != 0 && (keys != self.keys || self.run == u16::MAX) {
+            self.flush_run();
+        }
+        self.keys = keys;
+        self.run += 1;
+    }
+
+    fn flush_run(&mut self) {
+        self.data.extend_from_slice(&self.keys.bits().to_le_bytes());
+        self.data.extend_from_slice(&self.run.to_le_bytes());
+        self.run = 0;
+    }
+
+    #[allow(dead_code)]
+    pub fn frames(&self) -> usize {
+        self.data[RECORDING_MAGIC.len()..]
+            .chunks_exact(4)
+            .map(|run| usize::from(u16::from_le_bytes(run[2..4].try_into().unwrap())))
+            .sum::<usize>()
+            + usize::from(self.run)
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.run != 0 {
+            self.flush_run();
+        }
+        self.data
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub enum PlaybackError {
+    BadMagic,
+    Truncated,
+}
+
+pub struct Playback {
+    data: Cow<'static, [u8]>,
+    index: usize,
+    keys: Keys,
+    left: u16,
+}
+
+#[allow(dead_code)]
+impl Playback {
+    pub fn new(data: Cow<'static, [u8]>) -> Result<Self, PlaybackError> {
+        if !data.starts_with(RECORDING_MAGIC) {
+            return Err(PlaybackError::BadMagic);
+        }
+        if !(data.len() - RECORDING_MAGIC.len()).is_multiple_of(4) {
+            return Err(PlaybackError::Truncated);
+        }
+        Ok(Playback {
+            data,
+            index: RECORDING_MAGIC.len(),
+            keys: Keys::empty(),
+            left: 0,
+        })
+    }
+
+    pub fn from_file(file: &RomFile) -> Result<Self, PlaybackError> {
+        Self::new(Cow::Borrowed(file.as_bytes()))
+    }
+
+    pub fn from_recording(recording: Vec<u8>) -> Result<Self, PlaybackError> {
+        Self::new(Cow::Owned(recording))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.left == 0 && self.index >= self.data.len()
+    }
+}
+
+impl Iterator for Playback {
+    type Item = Keys;
+
+    fn next(&mut self) -> Option<Keys> {
+        while self.left == 0 {
+            let run = self.data.get(self.index..self.index + 4)?;
+            self.keys = Keys::from_bits_truncate(u16::from_le_bytes(run[0..2].try_into().unwrap()));
+            self.left = u16::from_le_bytes(run[2..4].try_into().unwrap());
+            self.index += 4;
+        }
+        self.left -= 1;
+        Some(self.keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let frames = [
+            Keys::empty(),
+            Keys::A,
+            Keys::A,
+            Keys::A | Keys::B,
+            Keys::empty(),
+            Keys::empty(),
+            Keys::START | Keys::SELECT,
+        ];
+        let mut recorder = Recorder::new();
+        for keys in frames.iter() {
+            recorder.record(*keys);
+        }
+        assert_eq!(recorder.frames(), frames.len());
+
+        let recording = recorder.finish();
+        assert_eq!(recording.len(), RECORDING_MAGIC.len() + 5 * 4);
+        let mut playback = Playback::from_recording(recording).unwrap();
+        for keys in frames.iter() {
+            assert!(!playback.is_finished());
+            assert_eq!(playback.next(), Some(*keys));
+        }
+        assert!(playback.is_finished());
+        assert_eq!(playback.next(), None);
+    }
+
+    #[test]
+    fn long_run_is_split() {
+        let mut recorder = Recorder::new();
+        for _ in 0..usize::from(u16::MAX) + 3 {
+            recorder.record(Keys::LEFT);
+        }
+        let recording = recorder.finish();
+        assert_eq!(recording.len(), RECORDING_MAGIC.len() + 2 * 4);
+        let playback = Playback::from_recording(recording).unwrap();
+        assert!(playback
+            .take_while(|keys| *keys == Keys::LEFT)
+            .eq(core::iter::repeat_n(Keys::LEFT, usize::from(u16::MAX) + 3)));
+    }
+
+    #[test]
+    fn bad_images() {
+        let empty = Playback::from_recording(Vec::new());
+        assert!(matches!(empty, Err(PlaybackError::BadMagic)));
+
+        let mut recording = Recorder::new();
+        recording.record(Keys::A);
+        let mut recording = recording.finish();
+        recording[0] = b'X';
+        let bad_magic = Playback::from_recording(recording.clone());
+        assert!(matches!(bad_magic, Err(PlaybackError::BadMagic)));
+
+        recording[0] = RECORDING_MAGIC[0];
+        recording.pop();
+        let truncated = Playback::from_recording(recording);
+        assert!(matches!(truncated, Err(PlaybackError::Truncated)));
+    }
+}
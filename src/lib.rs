@@ -1,28 +1,51 @@
 #![feature(default_alloc_error_handler)]
 #![feature(allocator_api)]
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![warn(unsafe_op_in_unsafe_fn)]
 
 extern crate alloc;
 
+mod assets;
+#[cfg(not(test))]
 mod c_support;
 mod debug_print;
+#[cfg(not(test))]
 mod fast_mem;
 mod file;
+mod free_list;
+mod gpio;
+mod io;
+mod keypad;
 mod lock;
+mod lockstep;
+#[cfg(not(test))]
+mod multiboot;
 mod once;
+mod rtc;
+mod save;
+mod serial;
+mod sound;
 mod util;
+#[cfg(not(test))]
 mod video;
 mod volatile;
 
 use alloc::boxed::Box;
+#[cfg(not(test))]
 use core::arch::asm;
+#[cfg(not(test))]
 use core::panic::PanicInfo;
+#[cfg(not(test))]
 use core::ptr;
 
+#[cfg(not(test))]
 use file::RomFile;
+#[cfg(not(test))]
+use keypad::{ComboAction, Keys};
 
+// The tests run on the host, where none of the hardware setup applies
+#[cfg(not(test))]
 #[panic_handler]
 fn panic_handle(panic_info: &PanicInfo) -> ! {
     use core::fmt::Write;
@@ -31,10 +54,12 @@ fn panic_handle(panic_info: &PanicInfo) -> ! {
     loop {}
 }
 
+#[cfg(not(test))]
 extern "C" {
     fn irq_handle();
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 extern "C" fn main() {
     unsafe {
@@ -57,7 +82,12 @@ extern "C" fn main() {
         ptr::write_volatile(0x400010C as *mut u32, 0xC00000); // min reload, start timer with irqs
     }
 
-    let file_test = RomFile::open("test.txt").unwrap();
+    keypad::arm_combo(
+        Keys::A | Keys::B | Keys::START | Keys::SELECT,
+        ComboAction::SoftReset,
+    );
+
+    let file_test = assets::TEST.open();
 
     println!("{}", file_test.as_str().unwrap());
 
@@ -80,7 +110,7 @@ extern "C" fn main() {
 
     println!("SP_IRQ: {:x}", sp_irq);
 
-    let file = RomFile::open("img/gba_yeen.img").unwrap();
+    let file = assets::IMG_GBA_YEEN.open();
     fast_mem::call_on_fast_stack(|| video::display_bitmap_file(file));
 
     #[allow(clippy::empty_loop)]
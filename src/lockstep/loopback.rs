@@ -0,0 +1,92 @@
+use super::{Link, MAX_PLAYERS};
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+struct Hub {
+    queued: Vec<VecDeque<u16>>,
+    received: Vec<VecDeque<[u16; MAX_PLAYERS]>>,
+    random: u32,
+    loss: u32,
+    late_id: bool,
+    transferred: bool,
+}
+
+impl Hub {
+    // Like multiplay, player 0 is the parent and there is one transfer for every word it sends
+    fn run(&mut self) {
+        while let Some(parent) = self.queued[0].pop_front() {
+            let mut words = [0xFFFF; MAX_PLAYERS];
+            words[0] = parent;
+            for (word, queued) in words.iter_mut().zip(self.queued.iter_mut()).skip(1) {
+                *word = queued.pop_front().unwrap_or(0xFFFF);
+            }
+            // Xorshift, so runs repeat exactly
+            self.random ^= self.random << 13;
+            self.random ^= self.random >> 17;
+            self.random ^= self.random << 5;
+            if self.loss != 0 && self.random.is_multiple_of(self.loss) {
+                words = [0xFFFF; MAX_PLAYERS];
+            }
+            for received in self.received.iter_mut() {
+                received.push_back(words);
+            }
+            self.transferred = true;
+        }
+    }
+}
+
+// In memory stand in for the link cable, so the lockstep code can run without hardware
+pub struct Loopback {
+    hub: Rc<RefCell<Hub>>,
+    player: usize,
+}
+
+#[allow(dead_code)]
+impl Loopback {
+    // One end for each player, about one in `loss` transfers fails or none do if it is 0. With
+    // `late_id` the ends don't know their player until the first transfer, like multiplay.
+    pub fn connect(players: usize, loss: u32, late_id: bool) -> Vec<Loopback> {
+        assert!(players <= MAX_PLAYERS, "Too many players for the loopback");
+        let hub = Rc::new(RefCell::new(Hub {
+            queued: (0..players).map(|_| VecDeque::new()).collect(),
+            received: (0..players).map(|_| VecDeque::new()).collect(),
+            random: 0x2545F491,
+            loss,
+            late_id,
+            transferred: false,
+        }));
+        (0..players)
+            .map(|player| Loopback {
+                hub: hub.clone(),
+                player,
+            })
+            .collect()
+    }
+}
+
+impl Link for Loopback {
+    fn local_player(&self) -> Option<usize> {
+        let hub = self.hub.borrow();
+        if hub.late_id && !hub.transferred {
+            return None;
+        }
+        Some(self.player)
+    }
+
+    fn send(&mut self, word: u16) -> bool {
+        self.hub.borrow_mut().queued[self.player].push_back(word);
+        true
+    }
+
+    fn receive(&mut self) -> Option<[u16; MAX_PLAYERS]> {
+        let mut hub = self.hub.borrow_mut();
+        hub.run();
+        hub.received[self.player].pop_front()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.hub.borrow().queued[self.player].is_empty()
+    }
+}
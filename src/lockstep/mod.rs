@@ -0,0 +1,441 @@
+use crate::save::crc32_update;
+use crate::serial::Multiplay;
+
+mod loopback;
+#[allow(unused_imports)]
+pub use loopback::Loopback;
+
+pub const MAX_PLAYERS: usize = 4;
+
+const RETRY_POLLS: u32 = 8;
+const TIMEOUT_POLLS: u32 = 240;
+const FRAME_MASK: u16 = 0xFFF;
+
+// Every word carries a tag in its top 4 bits so receivers can find the start of the next packet
+// after a lost transfer, which reads as 0xFFFF
+const TAG_MASK: u16 = 0xF000;
+const TAGS: [u16; 4] = [0xA000, 0x0000, 0x1000, 0x2000]; // Frame, input, checksum, check
+const TAG_RESEND: u16 = 0xB000; // Frame of a packet sent again for a unit that is behind
+
+pub trait Link {
+    // Can be None until the first transfer is done
+    fn local_player(&self) -> Option<usize>;
+
+    // Queues a word for the next transfer, returns false if there is no room
+    fn send(&mut self, word: u16) -> bool;
+
+    // What each player sent in the oldest finished transfer
+    fn receive(&mut self) -> Option<[u16; MAX_PLAYERS]>;
+
+    // Nothing is queued that hasn't gone out yet
+    fn is_idle(&self) -> bool;
+}
+
+impl Link for Multiplay {
+    fn local_player(&self) -> Option<usize> {
+        self.player_id().map(usize::from)
+    }
+
+    fn send(&mut self, word: u16) -> bool {
+        Multiplay::send(self, word)
+    }
+
+    fn receive(&mut self) -> Option<[u16; MAX_PLAYERS]> {
+        Multiplay::receive(self)
+    }
+
+    fn is_idle(&self) -> bool {
+        Multiplay::is_idle(self)
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum LockstepError {
+    #[allow(dead_code)]
+    Desync { frame: u16 },
+    #[allow(dead_code)]
+    Timeout { frame: u16 },
+}
+
+#[derive(Clone, Copy)]
+struct Packet {
+    frame: u16,
+    input: u16,
+    checksum: u16,
+}
+
+impl Packet {
+    fn check(&self) -> u16 {
+        (self.frame ^ self.input ^ self.checksum ^ 0x5A5) & 0xFFF
+    }
+
+    fn to_words(self, resend: bool) -> [u16; 4] {
+        let fields = [self.frame, self.input, self.checksum, self.check()];
+        let mut words = [0; 4];
+        for (word, (tag, field)) in words.iter_mut().zip(TAGS.iter().zip(fields.iter())) {
+            *word = tag | field;
+        }
+        if resend {
+            words[0] = TAG_RESEND | self.frame;
+        }
+        words
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Parser {
+    words: [u16; 4],
+    len: usize,
+    resend: bool,
+}
+
+impl Parser {
+    const fn new() -> Self {
+        Parser {
+            words: [0; 4],
+            len: 0,
+            resend: false,
+        }
+    }
+
+    // Returns the packet and whether it was a resend
+    fn push(&mut self, word: u16) -> Option<(Packet, bool)> {
+        if word & TAG_MASK == TAGS[0] || word & TAG_MASK == TAG_RESEND {
+            self.len = 0;
+            self.resend = word & TAG_MASK == TAG_RESEND;
+        } else if self.len == 0 || word & TAG_MASK != TAGS[self.len] {
+            self.len = 0;
+            return None;
+        }
+        self.words[self.len] = word & !TAG_MASK;
+        self.len += 1;
+        if self.len < self.words.len() {
+            return None;
+        }
+
+        self.len = 0;
+        let packet = Packet {
+            frame: self.words[0],
+            input: self.words[1],
+            checksum: self.words[2],
+        };
+        if packet.check() == self.words[3] {
+            Some((packet, self.resend))
+        } else {
+            None
+        }
+    }
+}
+
+// Keeps 2 to 4 units on the same frame by exchanging every unit's input before any of them runs
+// it. Each packet also has a checksum of the game state, rolled over every frame so far, which
+// every unit must agree on. Lost packets are sent again until the timeout, along with the
+// previous frame's packet for any unit that is still a frame behind.
+pub struct Lockstep<L> {
+    link: L,
+    players: usize,
+    local: Option<usize>,
+    frame: u16,
+    checksum: u32,
+    sent: Option<Packet>,
+    previous: Option<Packet>,
+    parsers: [Parser; MAX_PLAYERS],
+    current: [Option<Packet>; MAX_PLAYERS],
+    early: [Option<Packet>; MAX_PLAYERS],
+    polls: u32,
+}
+
+#[allow(dead_code)]
+impl<L: Link> Lockstep<L> {
+    pub fn new(link: L, players: usize) -> Self {
+        assert!(
+            (2..=MAX_PLAYERS).contains(&players),
+            "Lockstep needs 2 to 4 players"
+        );
+        Lockstep {
+            link,
+            players,
+            local: None,
+            frame: 0,
+            checksum: 0,
+            sent: None,
+            previous: None,
+            parsers: [Parser::new(); MAX_PLAYERS],
+            current: [None; MAX_PLAYERS],
+            early: [None; MAX_PLAYERS],
+            polls: 0,
+        }
+    }
+
+    // Wraps after 4096 frames
+    pub fn frame(&self) -> u16 {
+        self.frame
+    }
+
+    // Only known once a transfer has gone through
+    pub fn local_player(&self) -> Option<usize> {
+        self.local
+    }
+
+    pub fn into_link(self) -> L {
+        self.link
+    }
+
+    // Sends this unit's input for the current frame, only the low 12 bits are kept. `state` is
+    // whatever the game wants compared between units.
+    pub fn start_frame(&mut self, input: u16, state: &[u8]) {
+        assert!(self.sent.is_none(), "Frame was already started");
+        self.checksum = crc32_update(self.checksum, state);
+        let packet = Packet {
+            frame: self.frame,
+            input: input & 0xFFF,
+            checksum: (self.checksum ^ (self.checksum >> 16)) as u16 & 0xFFF,
+        };
+        if let Some(local) = self.local {
+            self.current[local] = Some(packet);
+        }
+        self.sent = Some(packet);
+        self.polls = 0;
+        self.send(packet, false);
+    }
+
+    // A packet that doesn't fit is sent again on the next retry
+    fn send(&mut self, packet: Packet, resend: bool) {
+        for word in packet.to_words(resend).iter() {
+            if !self.link.send(*word) {
+                break;
+            }
+        }
+    }
+
+    // Should be called once per frame until it returns every player's input, players past
+    // `players` read as 0
+    pub fn poll(&mut self) -> Result<Option<[u16; MAX_PLAYERS]>, LockstepError> {
+        while let Some(words) = self.link.receive() {
+            let local = match self.local {
+                Some(local) => local,
+                None => match self.link.local_player() {
+                    Some(local) => self.learn_local(local),
+                    None => continue, // Can't tell which words are ours, retries cover it
+                },
+            };
+            for (player, word) in words.iter().enumerate().take(self.players) {
+                if player == local {
+                    continue;
+                }
+                if let Some((packet, resend)) = self.parsers[player].push(*word) {
+                    self.accept(player, packet, resend);
+                }
+            }
+        }
+
+        let sent = match self.sent {
+            Some(sent) => sent,
+            None => return Ok(None),
+        };
+        if self.current[..self.players].iter().all(Option::is_some) {
+            return self.finish_frame().map(Some);
+        }
+
+        self.polls += 1;
+        if self.polls >= TIMEOUT_POLLS {
+            return Err(LockstepError::Timeout { frame: self.frame });
+        }
+        // Only units that drive the transfers can get them out, so retries wait for the last
+        // ones to go instead of piling up behind them
+        if self.polls.is_multiple_of(RETRY_POLLS) && self.link.is_idle() {
+            // A unit that is a frame behind may still need the last one
+            if let Some(previous) = self.previous {
+                self.send(previous, true);
+            }
+            self.send(sent, false);
+        }
+        Ok(None)
+    }
+
+    fn learn_local(&mut self, local: usize) -> usize {
+        self.local = Some(local);
+        self.current[local] = self.sent;
+        local
+    }
+
+    fn accept(&mut self, player: usize, packet: Packet, resend: bool) {
+        if packet.frame == self.frame {
+            self.current[player] = Some(packet);
+        } else if packet.frame == (self.frame + 1) & FRAME_MASK {
+            self.early[player] = Some(packet);
+        } else if packet.frame == self.frame.wrapping_sub(1) & FRAME_MASK && !resend {
+            // They are still waiting on our last packet. Resends don't count since they are only
+            // for units that are behind, answering those would bounce packets back and forth.
+            if let Some(previous) = self.previous {
+                self.send(previous, true);
+            }
+        }
+    }
+
+    fn finish_frame(&mut self) -> Result<[u16; MAX_PLAYERS], LockstepError> {
+        // Packets are only taken once our own id is known, so it is by now
+        let checksum = self.current[self.local.unwrap()].map(|packet| packet.checksum);
+        let mut inputs = [0; MAX_PLAYERS];
+        for (input, packet) in inputs.iter_mut().zip(self.current[..self.players].iter()) {
+            let packet = packet.unwrap();
+            if Some(packet.checksum) != checksum {
+                return Err(LockstepError::Desync { frame: self.frame });
+            }
+            *input = packet.input;
+        }
+
+        self.previous = self.sent.take();
+        self.current = self.early;
+        self.early = [None; MAX_PLAYERS];
+        self.frame = (self.frame + 1) & FRAME_MASK;
+        Ok(inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const FRAMES: usize = 40;
+
+    fn input(player: usize, frame: usize) -> u16 {
+        (player * 0x100 + frame) as u16 & 0xFFF
+    }
+
+    fn units(players: usize, loss: u32, late_id: bool) -> Vec<Lockstep<Loopback>> {
+        Loopback::connect(players, loss, late_id)
+            .into_iter()
+            .map(|link| Lockstep::new(link, players))
+            .collect()
+    }
+
+    // Ticks the units in turn like separate consoles until each has finished `FRAMES` frames or
+    // failed. Units that are done keep going since the others may still need their packets.
+    // `units` are in player order, which they may not know yet.
+    fn run<F>(units: &mut [Lockstep<Loopback>], state: F) -> Vec<Result<(), LockstepError>>
+    where
+        F: Fn(usize, usize) -> u8,
+    {
+        let players = units[0].players;
+        let mut seen = vec![Vec::new(); units.len()];
+        let mut errors: Vec<Option<LockstepError>> = units.iter().map(|_| None).collect();
+        let mut started = vec![false; units.len()];
+        for _ in 0..100_000 {
+            let pending = (0..units.len())
+                .any(|player| errors[player].is_none() && seen[player].len() < FRAMES);
+            if !pending {
+                break;
+            }
+            for (player, lockstep) in units.iter_mut().enumerate() {
+                if errors[player].is_some() {
+                    continue;
+                }
+                let frame = seen[player].len();
+                if !started[player] {
+                    lockstep.start_frame(input(player, frame), &[state(player, frame)]);
+                    started[player] = true;
+                }
+                match lockstep.poll() {
+                    Ok(Some(inputs)) => {
+                        seen[player].push(inputs);
+                        started[player] = false;
+                    }
+                    Ok(None) => {}
+                    Err(error) => errors[player] = Some(error),
+                }
+            }
+        }
+
+        seen.into_iter()
+            .zip(errors)
+            .map(|(seen, error)| {
+                if let Some(error) = error {
+                    return Err(error);
+                }
+                assert!(seen.len() >= FRAMES, "Units got stuck");
+                for (frame, inputs) in seen.iter().take(FRAMES).enumerate() {
+                    for (player, got) in inputs.iter().enumerate() {
+                        let expected = if player < players {
+                            input(player, frame)
+                        } else {
+                            0
+                        };
+                        assert_eq!(*got, expected, "Player {} on frame {}", player, frame);
+                    }
+                }
+                Ok(())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn in_order() {
+        for players in 2..=MAX_PLAYERS {
+            for late_id in [false, true].iter() {
+                let mut units = units(players, 0, *late_id);
+                assert!(run(&mut units, |_, frame| frame as u8)
+                    .iter()
+                    .all(Result::is_ok));
+                for (player, unit) in units.iter().enumerate() {
+                    assert!(unit.frame() as usize >= FRAMES);
+                    assert_eq!(unit.local_player(), Some(player));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn late_id() {
+        let mut units = units(2, 0, true);
+        units[1].start_frame(input(1, 0), &[0]);
+        assert!(matches!(units[1].poll(), Ok(None)));
+        assert_eq!(units[1].local_player(), None);
+
+        // The parent starts the first transfer, which already carries both packets
+        let inputs = [input(0, 0), input(1, 0), 0, 0];
+        units[0].start_frame(input(0, 0), &[0]);
+        assert!(matches!(units[0].poll(), Ok(Some(got)) if got == inputs));
+        assert_eq!(units[0].local_player(), Some(0));
+        assert!(matches!(units[1].poll(), Ok(Some(got)) if got == inputs));
+        assert_eq!(units[1].local_player(), Some(1));
+    }
+
+    #[test]
+    fn lost_transfers() {
+        for players in 2..=MAX_PLAYERS {
+            for late_id in [false, true].iter() {
+                let mut units = units(players, 6, *late_id);
+                assert!(run(&mut units, |_, frame| frame as u8)
+                    .iter()
+                    .all(Result::is_ok));
+            }
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        for players in 2..=MAX_PLAYERS {
+            // The last player never shows up
+            let mut units = units(players, 0, true);
+            units.pop();
+            for result in run(&mut units, |_, _| 0) {
+                assert!(matches!(result, Err(LockstepError::Timeout { frame: 0 })));
+            }
+        }
+    }
+
+    #[test]
+    fn desync() {
+        for players in 2..=MAX_PLAYERS {
+            let mut units = units(players, 0, true);
+            let state = |player, frame| if player == 1 && frame == 3 { 1 } else { 0 };
+            for result in run(&mut units, state) {
+                assert!(matches!(result, Err(LockstepError::Desync { frame: 3 })));
+            }
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use crate::file::RomFile;
+use crate::util::{get_timer, without_irq};
+use core::arch::asm;
+use core::ptr;
+
+const HEADER_SIZE: usize = 0xC0;
+const MIN_IMAGE_SIZE: usize = HEADER_SIZE + 0x100;
+const MAX_IMAGE_SIZE: usize = 0x40000;
+
+const PALETTE_DATA: u8 = 0xC1; // Logo colour and spin for the clients
+const DETECT_TRIES: u32 = 15;
+const SIXTEENTH_SECOND: u32 = 16; // Timer 3 overflows about 256 times a second
+const TRANSFER_TIMEOUT: u32 = 0x10000;
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub enum MultibootMode {
+    Normal256K,
+    Normal2M,
+    Multiplay,
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum MultibootError {
+    BadSize,
+    NotMaster,
+    NoClients,
+    Rejected,
+    Timeout,
+    TransferFailed,
+}
+
+// Parameter block for SWI 0x25, zeroed fields are for the bios
+#[repr(C)]
+struct MultibootParam {
+    reserved_1: [u32; 5],
+    handshake_data: u8,
+    padding: u8,
+    handshake_timeout: u16,
+    probe_count: u8,
+    client_data: [u8; 3],
+    palette_data: u8,
+    response_bit: u8,
+    client_bit: u8,
+    reserved_2: u8,
+    boot_srcp: *const u8,
+    boot_endp: *const u8,
+    masterp: *const u8,
+    reserved_3: [*const u8; 3],
+    system_work_2: [u32; 4],
+    send_flag: u8,
+    probe_target_bit: u8,
+    check_wait: u8,
+    server_type: u8,
+}
+
+fn wait(ticks: u32) {
+    let begin = get_timer();
+    while get_timer().wrapping_sub(begin) < ticks {}
+}
+
+fn client_bit(client: usize) -> u8 {
+    2 << client
+}
+
+impl MultibootMode {
+    fn swi_mode(self) -> u32 {
+        match self {
+            MultibootMode::Normal256K => 0,
+            MultibootMode::Multiplay => 1,
+            MultibootMode::Normal2M => 2,
+        }
+    }
+
+    fn init(self) -> Result<(), MultibootError> {
+        unsafe {
+            ptr::write_volatile(0x4000134 as *mut u16, 0); // Serial mode in rcnt
+            let control = match self {
+                MultibootMode::Normal256K => 0x1001, // 32 bit, internal clock
+                MultibootMode::Normal2M => 0x1003,
+                MultibootMode::Multiplay => 0x2003, // 115200 baud
+            };
+            ptr::write_volatile(0x4000128 as *mut u16, control); // Siocnt
+            if let MultibootMode::Multiplay = self {
+                // Si is low on the parent and sd is high once every unit is ready
+                let control = ptr::read_volatile(0x4000128 as *const u16);
+                if control & 0xC != 0x8 {
+                    return Err(MultibootError::NotMaster);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Sends to every client and returns what clients 1 to 3 sent back. Normal mode only has
+    // client 1, which answers in the top half of the 32 bit transfer.
+    fn transfer(self, value: u16) -> Result<[u16; 3], MultibootError> {
+        unsafe {
+            let control = ptr::read_volatile(0x4000128 as *const u16);
+            match self {
+                MultibootMode::Multiplay => {
+                    ptr::write_volatile(0x400012A as *mut u16, value); // Siomlt_send
+                }
+                _ => {
+                    ptr::write_volatile(0x4000120 as *mut u32, u32::from(value)); // Siodata32
+                    let mut timeout = TRANSFER_TIMEOUT;
+                    // The client pulls si low when it is ready for the next transfer
+                    while ptr::read_volatile(0x4000128 as *const u16) & 0x4 != 0 {
+                        timeout -= 1;
+                        if timeout == 0 {
+                            return Err(MultibootError::Timeout);
+                        }
+                    }
+                }
+            }
+            ptr::write_volatile(0x4000128 as *mut u16, control | 0x80); // Start
+
+            let mut timeout = TRANSFER_TIMEOUT;
+            while ptr::read_volatile(0x4000128 as *const u16) & 0x80 != 0 {
+                timeout -= 1;
+                if timeout == 0 {
+                    return Err(MultibootError::Timeout);
+                }
+            }
+
+            match self {
+                MultibootMode::Multiplay => Ok([
+                    ptr::read_volatile(0x4000122 as *const u16), // Siomulti1
+                    ptr::read_volatile(0x4000124 as *const u16), // Siomulti2
+                    ptr::read_volatile(0x4000126 as *const u16), // Siomulti3
+                ]),
+                _ => {
+                    let data = ptr::read_volatile(0x4000120 as *const u32);
+                    Ok([(data >> 16) as u16, 0xFFFF, 0xFFFF])
+                }
+            }
+        }
+    }
+
+    // Transfers `value` and checks every client in `clients` answered with `expected` in the
+    // bits of `mask`, the client's bit is or-ed into `expected` first
+    fn exchange(
+        self,
+        value: u16,
+        clients: u8,
+        mask: u16,
+        expected: u16,
+    ) -> Result<[u16; 3], MultibootError> {
+        let replies = self.transfer(value)?;
+        for (client, reply) in replies.iter().enumerate() {
+            let bit = client_bit(client);
+            if clients & bit != 0 && reply & mask != (expected | u16::from(bit)) & mask {
+                return Err(MultibootError::Rejected);
+            }
+        }
+        Ok(replies)
+    }
+
+    fn detect(self) -> Result<u8, MultibootError> {
+        for _ in 0..DETECT_TRIES {
+            let replies = self.transfer(0x6200)?;
+            let clients = replies
+                .iter()
+                .enumerate()
+                .filter(|(client, reply)| **reply == 0x7200 | u16::from(client_bit(*client)))
+                .fold(0, |clients, (client, _)| clients | client_bit(client));
+            if clients != 0 {
+                return Ok(clients);
+            }
+            wait(SIXTEENTH_SECOND);
+        }
+        Err(MultibootError::NoClients)
+    }
+}
+
+// Sends a multiboot image to the clients on the link cable and returns the bits of the clients
+// that got it. `image` starts with a normal 0xC0 byte header.
+#[allow(dead_code)]
+pub fn send(image: &'static [u8], mode: MultibootMode) -> Result<u8, MultibootError> {
+    if image.len() < MIN_IMAGE_SIZE || image.len() > MAX_IMAGE_SIZE {
+        return Err(MultibootError::BadSize);
+    }
+    mode.init()?;
+    let clients = mode.detect()?;
+    mode.exchange(0x6100 | u16::from(clients), clients, 0xFFFF, 0x7200)?;
+
+    for halfword in image[..HEADER_SIZE].chunks(2) {
+        let halfword = u16::from_le_bytes([halfword[0], halfword[1]]);
+        mode.exchange(halfword, clients, 0xFF, 0)?;
+    }
+    mode.exchange(0x6200, clients, 0xFF, 0)?;
+    mode.exchange(0x6200 | u16::from(clients), clients, 0xFFFF, 0x7200)?;
+
+    // Clients answer with their random data once they have shown the palette
+    let palette = 0x6300 | u16::from(PALETTE_DATA);
+    let begin = get_timer();
+    let replies = loop {
+        let replies = mode.transfer(palette)?;
+        let ready = replies
+            .iter()
+            .enumerate()
+            .all(|(client, reply)| clients & client_bit(client) == 0 || reply & 0xFF00 == 0x7300);
+        if ready {
+            break replies;
+        }
+        if get_timer().wrapping_sub(begin) > 16 * SIXTEENTH_SECOND {
+            return Err(MultibootError::Timeout);
+        }
+    };
+
+    // The bios seeds the encryption from the handshake and the client data
+    let mut client_data = [0xFF; 3];
+    for (client, reply) in replies.iter().enumerate() {
+        if clients & client_bit(client) != 0 {
+            client_data[client] = *reply as u8;
+        }
+    }
+    let handshake = client_data
+        .iter()
+        .fold(0x11u8, |sum, data| sum.wrapping_add(*data));
+    mode.exchange(0x6400 | u16::from(handshake), clients, 0xFF00, 0x7300)?;
+    wait(SIXTEENTH_SECOND);
+
+    // Lengths after the header must be a multiple of 16, the bios reads the rest as padding
+    let body_len = (image.len() - HEADER_SIZE + 15) & !15;
+    let param = MultibootParam {
+        reserved_1: [0; 5],
+        handshake_data: handshake,
+        padding: 0,
+        handshake_timeout: 0,
+        probe_count: 0,
+        client_data,
+        palette_data: PALETTE_DATA,
+        response_bit: 0,
+        client_bit: clients,
+        reserved_2: 0,
+        boot_srcp: image[HEADER_SIZE..].as_ptr(),
+        boot_endp: image[HEADER_SIZE..].as_ptr().wrapping_add(body_len),
+        masterp: ptr::null(),
+        reserved_3: [ptr::null(); 3],
+        system_work_2: [0; 4],
+        send_flag: 0,
+        probe_target_bit: 0,
+        check_wait: 0,
+        server_type: 0,
+    };
+
+    let failed = without_irq(|| unsafe {
+        let failed: u32;
+        asm!("SWI 0x25",
+             inout("r0") &param as *const MultibootParam as u32 => failed,
+             inout("r1") mode.swi_mode() => _,
+             out("r2") _,
+             out("r3") _,
+             out("r12") _);
+        failed
+    });
+    if failed != 0 {
+        return Err(MultibootError::TransferFailed);
+    }
+    Ok(clients)
+}
+
+#[allow(dead_code)]
+pub fn send_file(file: &RomFile, mode: MultibootMode) -> Result<u8, MultibootError> {
+    send(file.as_bytes(), mode)
+}
@@ -0,0 +1,238 @@
+use crate::gpio::Gpio;
+
+const SCK: u8 = 1;
+const SIO: u8 = 2;
+const CS: u8 = 4;
+
+const CMD_RESET: u8 = 0x60;
+const CMD_STATUS: u8 = 0x62;
+const CMD_DATE_TIME: u8 = 0x64;
+const CMD_TIME: u8 = 0x66;
+const CMD_READ: u8 = 1;
+
+const STATUS_24_HOUR: u8 = 0x40;
+const STATUS_POWER_LOST: u8 = 0x80;
+const HOUR_PM: u8 = 0x80;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Time {
+    pub hour: u8, // Always 0 to 23, whatever mode the clock is in
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTime {
+    pub year: u16, // 2000 to 2099
+    pub month: u8,
+    pub day: u8,
+    pub weekday: u8, // 0 is sunday
+    pub time: Time,
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum RtcError {
+    NotFound,
+    OutOfRange,
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xF)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+fn is_bcd(value: u8) -> bool {
+    value >> 4 < 10 && value & 0xF < 10
+}
+
+// Seiko S-3511 on the cart gpio port. Values are in bcd, commands go out most significant bit
+// first and data least significant bit first.
+pub struct Rtc {
+    gpio: Gpio,
+    hour_24: bool,
+}
+
+#[allow(dead_code)]
+impl Rtc {
+    // Resets the clock if it lost power, which sets it to midnight on 2000-01-01
+    pub fn detect() -> Result<Self, RtcError> {
+        let mut rtc = Rtc {
+            gpio: Gpio::new(),
+            hour_24: false,
+        };
+        let mut status = rtc.status();
+        if status & STATUS_POWER_LOST != 0 {
+            rtc.write_register(CMD_RESET, &[]);
+            status = rtc.status();
+        }
+        rtc.hour_24 = status & STATUS_24_HOUR != 0;
+
+        // Without a clock the port reads back as whatever the pins float to
+        let mut raw = [0; 7];
+        rtc.read_register(CMD_DATE_TIME, &mut raw);
+        let month = from_bcd(raw[1]);
+        let day = from_bcd(raw[2]);
+        if status == 0xFF
+            || !raw.iter().all(|byte| is_bcd(*byte & 0x7F))
+            || !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+        {
+            return Err(RtcError::NotFound);
+        }
+        Ok(rtc)
+    }
+
+    fn begin(&mut self) {
+        self.gpio.write(SCK);
+        self.gpio.write(SCK | CS);
+        self.gpio.set_direction(SCK | SIO | CS);
+    }
+
+    fn end(&mut self) {
+        self.gpio.write(SCK);
+        self.gpio.write(SCK);
+    }
+
+    fn write_bit(&mut self, bit: u8) {
+        let sio = if bit != 0 { SIO } else { 0 };
+        // The clock has to stay low for a few writes or the chip misses the bit
+        for _ in 0..4 {
+            self.gpio.write(sio | CS);
+        }
+        self.gpio.write(sio | SCK | CS);
+    }
+
+    fn read_bit(&mut self) -> u8 {
+        for _ in 0..4 {
+            self.gpio.write(CS);
+        }
+        self.gpio.write(SCK | CS);
+        (self.gpio.read() & SIO) >> 1
+    }
+
+    fn command(&mut self, command: u8) {
+        self.begin();
+        for bit in (0..8).rev() {
+            self.write_bit(command >> bit & 1);
+        }
+    }
+
+    fn write_register(&mut self, command: u8, data: &[u8]) {
+        self.command(command);
+        for byte in data {
+            for bit in 0..8 {
+                self.write_bit(byte >> bit & 1);
+            }
+        }
+        self.end();
+    }
+
+    fn read_register(&mut self, command: u8, out: &mut [u8]) {
+        self.command(command | CMD_READ);
+        self.gpio.set_direction(SCK | CS);
+        for byte in out.iter_mut() {
+            *byte = 0;
+            for bit in 0..8 {
+                *byte |= self.read_bit() << bit;
+            }
+        }
+        self.end();
+    }
+
+    fn status(&mut self) -> u8 {
+        let mut status = [0];
+        self.read_register(CMD_STATUS, &mut status);
+        status[0]
+    }
+
+    // In 12 hour mode the clock counts 0 to 11 with a pm flag
+    fn decode_time(&self, raw: &[u8]) -> Time {
+        let hour = from_bcd(raw[0] & 0x3F);
+        let hour = if self.hour_24 {
+            hour
+        } else if raw[0] & HOUR_PM != 0 {
+            hour % 12 + 12
+        } else {
+            hour % 12
+        };
+        Time {
+            hour,
+            minute: from_bcd(raw[1] & 0x7F),
+            second: from_bcd(raw[2] & 0x7F),
+        }
+    }
+
+    fn encode_time(&self, time: &Time) -> [u8; 3] {
+        let hour = if self.hour_24 {
+            to_bcd(time.hour)
+        } else if time.hour >= 12 {
+            to_bcd(time.hour - 12) | HOUR_PM
+        } else {
+            to_bcd(time.hour)
+        };
+        [hour, to_bcd(time.minute), to_bcd(time.second)]
+    }
+
+    pub fn date_time(&mut self) -> DateTime {
+        let mut raw = [0; 7];
+        self.read_register(CMD_DATE_TIME, &mut raw);
+        DateTime {
+            year: 2000 + u16::from(from_bcd(raw[0])),
+            month: from_bcd(raw[1] & 0x1F),
+            day: from_bcd(raw[2] & 0x3F),
+            weekday: raw[3] & 0x7,
+            time: self.decode_time(&raw[4..]),
+        }
+    }
+
+    pub fn time(&mut self) -> Time {
+        let mut raw = [0; 3];
+        self.read_register(CMD_TIME, &mut raw);
+        self.decode_time(&raw)
+    }
+
+    pub fn set_date_time(&mut self, date_time: &DateTime) -> Result<(), RtcError> {
+        let time = &date_time.time;
+        if !(2000..=2099).contains(&date_time.year)
+            || !(1..=12).contains(&date_time.month)
+            || !(1..=31).contains(&date_time.day)
+            || date_time.weekday > 6
+            || time.hour > 23
+            || time.minute > 59
+            || time.second > 59
+        {
+            return Err(RtcError::OutOfRange);
+        }
+        let [hour, minute, second] = self.encode_time(time);
+        let raw = [
+            to_bcd((date_time.year - 2000) as u8),
+            to_bcd(date_time.month),
+            to_bcd(date_time.day),
+            date_time.weekday,
+            hour,
+            minute,
+            second,
+        ];
+        self.write_register(CMD_DATE_TIME, &raw);
+        Ok(())
+    }
+
+    pub fn is_24_hour(&self) -> bool {
+        self.hour_24
+    }
+
+    // The hours are stored differently in each mode, so the time is written back after switching
+    pub fn set_24_hour(&mut self, hour_24: bool) -> Result<(), RtcError> {
+        if hour_24 == self.hour_24 {
+            return Ok(());
+        }
+        let date_time = self.date_time();
+        self.write_register(CMD_STATUS, &[if hour_24 { STATUS_24_HOUR } else { 0 }]);
+        self.hour_24 = hour_24;
+        self.set_date_time(&date_time)
+    }
+}
@@ -0,0 +1,183 @@
+use super::{check_bounds, SaveError, SaveMedia};
+use crate::util::without_irq;
+use core::cmp::min;
+use core::marker::PhantomData;
+use core::ptr;
+
+// Works for both 16M and 32M roms, the bigger ones only decode the top of the region
+const EEPROM_ADDRESS: usize = 0xDFFFF00;
+const BLOCK_SIZE: usize = 8;
+const WRITE_TIMEOUT: u32 = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EepromSize {
+    Bytes512,
+    Bytes8K,
+}
+
+impl EepromSize {
+    fn address_bits(self) -> usize {
+        match self {
+            EepromSize::Bytes512 => 6,
+            EepromSize::Bytes8K => 14,
+        }
+    }
+
+    pub fn bytes(self) -> usize {
+        match self {
+            EepromSize::Bytes512 => 512,
+            EepromSize::Bytes8K => 0x2000,
+        }
+    }
+}
+
+fn dma3_transfer(source: *const u16, dest: *mut u16, count: usize) {
+    unsafe {
+        ptr::write_volatile(0x40000D4 as *mut u32, source as u32); // Source
+        ptr::write_volatile(0x40000D8 as *mut u32, dest as u32); // Dest
+        ptr::write_volatile(0x40000DC as *mut u16, count as u16); // Count
+        ptr::write_volatile(0x40000DE as *mut u16, 0x8000); // Start 16 bit transfer
+        while ptr::read_volatile(0x40000DE as *const u16) & 0x8000 != 0 {}
+    }
+}
+
+// The eeprom takes one bit per halfword, most significant bit first
+fn push_bits(bits: &mut [u16], start: usize, value: u64, count: usize) -> usize {
+    for i in 0..count {
+        bits[start + i] = ((value >> (count - 1 - i)) & 1) as u16;
+    }
+    start + count
+}
+
+fn read_block(size: EepromSize, block: usize) -> u64 {
+    let mut request = [0; 2 + 14 + 1];
+    let mut len = push_bits(&mut request, 0, 0b11, 2);
+    len = push_bits(&mut request, len, block as u64, size.address_bits());
+    len += 1; // Stop bit
+
+    let mut reply = [0; 68];
+    without_irq(|| {
+        dma3_transfer(request.as_ptr(), EEPROM_ADDRESS as *mut u16, len);
+        dma3_transfer(
+            EEPROM_ADDRESS as *const u16,
+            reply.as_mut_ptr(),
+            reply.len(),
+        );
+    });
+    // The first 4 bits are junk
+    reply[4..]
+        .iter()
+        .fold(0, |value, bit| (value << 1) | u64::from(bit & 1))
+}
+
+fn write_block(size: EepromSize, block: usize, value: u64) -> Result<(), SaveError> {
+    let mut request = [0; 2 + 14 + 64 + 1];
+    let mut len = push_bits(&mut request, 0, 0b10, 2);
+    len = push_bits(&mut request, len, block as u64, size.address_bits());
+    len = push_bits(&mut request, len, value, 64);
+    len += 1; // Stop bit
+
+    without_irq(|| dma3_transfer(request.as_ptr(), EEPROM_ADDRESS as *mut u16, len));
+    for _ in 0..WRITE_TIMEOUT {
+        if unsafe { ptr::read_volatile(EEPROM_ADDRESS as *const u16) } & 1 != 0 {
+            return Ok(());
+        }
+    }
+    Err(SaveError::Timeout)
+}
+
+pub struct Eeprom {
+    size: EepromSize,
+    _priv: PhantomData<*mut ()>,
+}
+
+#[allow(dead_code)]
+impl Eeprom {
+    // For carts whose size is known, detect() has to write to the chip
+    pub fn new(size: EepromSize) -> Self {
+        unsafe {
+            // Eeprom is on wait state 2 and needs 8 cycle accesses
+            let wait_control = ptr::read_volatile(0x4000204 as *const u16);
+            ptr::write_volatile(0x4000204 as *mut u16, (wait_control & !0x700) | 0x300);
+        }
+        Eeprom {
+            size,
+            _priv: PhantomData,
+        }
+    }
+
+    // Writes the inverse of block 0 with 14 bit addressing and checks it reads back. A 512 byte
+    // chip takes the long request as a write to its block 0, so block 0 is restored in whichever
+    // size it turns out to be. Losing power in between leaves block 0 garbled.
+    pub fn detect() -> Result<Self, SaveError> {
+        let mut eeprom = Eeprom::new(EepromSize::Bytes512);
+        let small_block = read_block(EepromSize::Bytes512, 0);
+        let large_block = read_block(EepromSize::Bytes8K, 0);
+
+        let probe = !large_block;
+        let is_large = write_block(EepromSize::Bytes8K, 0, probe).is_ok()
+            && read_block(EepromSize::Bytes8K, 0) == probe;
+        if is_large {
+            eeprom.size = EepromSize::Bytes8K;
+            write_block(eeprom.size, 0, large_block)?;
+        } else {
+            write_block(eeprom.size, 0, small_block)?;
+        }
+        Ok(eeprom)
+    }
+
+    pub fn size(&self) -> EepromSize {
+        self.size
+    }
+
+    pub fn read_block(&mut self, block: usize, buffer: &mut [u8; BLOCK_SIZE]) {
+        *buffer = read_block(self.size, block).to_be_bytes();
+    }
+
+    pub fn write_block(&mut self, block: usize, data: &[u8; BLOCK_SIZE]) -> Result<(), SaveError> {
+        write_block(self.size, block, u64::from_be_bytes(*data))
+    }
+}
+
+impl SaveMedia for Eeprom {
+    fn len(&self) -> usize {
+        self.size.bytes()
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), SaveError> {
+        check_bounds(self.size.bytes(), offset, buffer.len())?;
+        let mut block = [0; BLOCK_SIZE];
+        let mut done = 0;
+        while done < buffer.len() {
+            let offset = offset + done;
+            let within = offset % BLOCK_SIZE;
+            let count = min(BLOCK_SIZE - within, buffer.len() - done);
+            self.read_block(offset / BLOCK_SIZE, &mut block);
+            buffer[done..done + count].copy_from_slice(&block[within..within + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError> {
+        check_bounds(self.size.bytes(), offset, data.len())?;
+        let mut block = [0; BLOCK_SIZE];
+        let mut done = 0;
+        while done < data.len() {
+            let offset = offset + done;
+            let within = offset % BLOCK_SIZE;
+            let count = min(BLOCK_SIZE - within, data.len() - done);
+            self.read_block(offset / BLOCK_SIZE, &mut block);
+            if block[within..within + count] != data[done..done + count] {
+                block[within..within + count].copy_from_slice(&data[done..done + count]);
+                self.write_block(offset / BLOCK_SIZE, &block)?;
+            }
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+}
@@ -0,0 +1,247 @@
+use super::sram::{sram_read, SRAM_BASE as FLASH_BASE};
+use super::{check_bounds, set_save_wait, SaveError, SaveMedia};
+use crate::util::without_irq;
+use alloc::vec;
+use core::cmp::min;
+use core::marker::PhantomData;
+use core::ptr;
+
+const BANK_SIZE: usize = 0x10000;
+const SECTOR_SIZE: usize = 0x1000;
+const ATMEL_PAGE_SIZE: usize = 128;
+
+// Poll loop iterations, a little under a microsecond each
+const ERASE_TIMEOUT: u32 = 0x40000;
+const PROGRAM_TIMEOUT: u32 = 0x1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashChip {
+    Sst,
+    Macronix64,
+    Panasonic,
+    Atmel,
+    Sanyo,
+    Macronix128,
+}
+
+impl FlashChip {
+    // Device id in the high byte, manufacturer in the low
+    fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0xD4BF => Some(FlashChip::Sst),
+            0x1CC2 => Some(FlashChip::Macronix64),
+            0x1B32 => Some(FlashChip::Panasonic),
+            0x3D1F => Some(FlashChip::Atmel),
+            0x1362 => Some(FlashChip::Sanyo),
+            0x09C2 => Some(FlashChip::Macronix128),
+            _ => None,
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            FlashChip::Sanyo | FlashChip::Macronix128 => 2 * BANK_SIZE,
+            _ => BANK_SIZE,
+        }
+    }
+}
+
+// Only reads of the flash chip itself are affected while it is busy, they return its status
+// instead of data. Rom is a separate chip, so the irq handlers keep running and irqs are only held
+// off while a command sequence is written, never while polling.
+#[link_section = ".fast_text"]
+fn flash_command(command: u8) {
+    unsafe {
+        ptr::write_volatile((FLASH_BASE + 0x5555) as *mut u8, 0xAA);
+        ptr::write_volatile((FLASH_BASE + 0x2AAA) as *mut u8, 0x55);
+        ptr::write_volatile((FLASH_BASE + 0x5555) as *mut u8, command);
+    }
+}
+
+#[link_section = ".fast_text"]
+fn wait_for(address: usize, value: u8, timeout: u32) -> Result<(), SaveError> {
+    for _ in 0..timeout {
+        if unsafe { ptr::read_volatile((FLASH_BASE + address) as *const u8) } == value {
+            return Ok(());
+        }
+    }
+    // Reset, Macronix chips stay busy after a failure otherwise
+    without_irq(|| flash_command(0xF0));
+    Err(SaveError::Timeout)
+}
+
+#[link_section = ".fast_text"]
+fn read_id() -> u16 {
+    flash_command(0x90);
+    let id = unsafe {
+        u16::from(ptr::read_volatile(FLASH_BASE as *const u8))
+            | u16::from(ptr::read_volatile((FLASH_BASE + 1) as *const u8)) << 8
+    };
+    flash_command(0xF0);
+    id
+}
+
+#[link_section = ".fast_text"]
+fn switch_bank(bank: u8) {
+    flash_command(0xB0);
+    unsafe { ptr::write_volatile(FLASH_BASE as *mut u8, bank) };
+}
+
+#[link_section = ".fast_text"]
+fn erase_sector(address: usize) -> Result<(), SaveError> {
+    without_irq(|| {
+        flash_command(0x80);
+        unsafe {
+            ptr::write_volatile((FLASH_BASE + 0x5555) as *mut u8, 0xAA);
+            ptr::write_volatile((FLASH_BASE + 0x2AAA) as *mut u8, 0x55);
+            ptr::write_volatile((FLASH_BASE + address) as *mut u8, 0x30);
+        }
+    });
+    wait_for(address, 0xFF, ERASE_TIMEOUT)
+}
+
+// Programming can only clear bits, erased bytes are skipped
+#[link_section = ".fast_text"]
+fn program_bytes(address: usize, data: &[u8]) -> Result<(), SaveError> {
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == 0xFF {
+            continue;
+        }
+        without_irq(|| {
+            flash_command(0xA0);
+            unsafe { ptr::write_volatile((FLASH_BASE + address + i) as *mut u8, *byte) };
+        });
+        wait_for(address + i, *byte, PROGRAM_TIMEOUT)?;
+    }
+    Ok(())
+}
+
+// Atmel chips erase and program a whole page at once. The page has to be written without long
+// gaps between bytes, so that is one command sequence.
+#[link_section = ".fast_text"]
+fn program_page(address: usize, page: &[u8]) -> Result<(), SaveError> {
+    without_irq(|| {
+        flash_command(0xA0);
+        for (i, byte) in page.iter().enumerate() {
+            unsafe { ptr::write_volatile((FLASH_BASE + address + i) as *mut u8, *byte) };
+        }
+    });
+    let last = address + page.len() - 1;
+    wait_for(last, page[page.len() - 1], ERASE_TIMEOUT)
+}
+
+pub struct Flash {
+    chip: FlashChip,
+    bank: Option<u8>,
+    _priv: PhantomData<*mut ()>,
+}
+
+#[allow(dead_code)]
+impl Flash {
+    pub fn detect() -> Result<Self, SaveError> {
+        set_save_wait();
+        let id = without_irq(read_id);
+        let chip = FlashChip::from_id(id).ok_or(SaveError::UnknownDevice(id))?;
+        Ok(Flash {
+            chip,
+            bank: None,
+            _priv: PhantomData,
+        })
+    }
+
+    pub fn chip(&self) -> FlashChip {
+        self.chip
+    }
+
+    // Selects the bank holding offset and returns the address within it
+    fn select(&mut self, offset: usize) -> usize {
+        let bank = (offset / BANK_SIZE) as u8;
+        if self.chip.size() > BANK_SIZE && self.bank != Some(bank) {
+            without_irq(|| switch_bank(bank));
+            self.bank = Some(bank);
+        }
+        offset % BANK_SIZE
+    }
+
+    pub fn erase_sector(&mut self, offset: usize) -> Result<(), SaveError> {
+        check_bounds(self.chip.size(), offset, SECTOR_SIZE)?;
+        let address = self.select(offset - offset % SECTOR_SIZE);
+        erase_sector(address)
+    }
+
+    fn write_block(
+        &mut self,
+        block: usize,
+        within: usize,
+        data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), SaveError> {
+        self.read(block, buffer)?;
+        let old = &buffer[within..within + data.len()];
+        if old == data {
+            return Ok(());
+        }
+        let needs_erase = old
+            .iter()
+            .zip(data.iter())
+            .any(|(old, new)| old & new != *new);
+        buffer[within..within + data.len()].copy_from_slice(data);
+
+        let address = self.select(block);
+        if self.chip == FlashChip::Atmel {
+            program_page(address, buffer)
+        } else if needs_erase {
+            erase_sector(address)?;
+            program_bytes(address, buffer)
+        } else {
+            program_bytes(address + within, data)
+        }
+    }
+}
+
+impl SaveMedia for Flash {
+    fn len(&self) -> usize {
+        self.chip.size()
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), SaveError> {
+        check_bounds(self.chip.size(), offset, buffer.len())?;
+        let mut done = 0;
+        while done < buffer.len() {
+            let offset = offset + done;
+            let count = min(BANK_SIZE - offset % BANK_SIZE, buffer.len() - done);
+            let address = self.select(offset);
+            sram_read(address, &mut buffer[done..done + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError> {
+        check_bounds(self.chip.size(), offset, data.len())?;
+        let block_size = self.block_size();
+        let mut buffer = vec![0; block_size];
+        let mut done = 0;
+        while done < data.len() {
+            let offset = offset + done;
+            let within = offset % block_size;
+            let count = min(block_size - within, data.len() - done);
+            self.write_block(
+                offset - within,
+                within,
+                &data[done..done + count],
+                &mut buffer,
+            )?;
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        if self.chip == FlashChip::Atmel {
+            ATMEL_PAGE_SIZE
+        } else {
+            SECTOR_SIZE
+        }
+    }
+}
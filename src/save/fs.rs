@@ -0,0 +1,345 @@
+use super::{Journal, SaveError, SaveMedia, SlotError};
+use crate::file::{normalize_path, OpenError, RomFile};
+use crate::io::{seek_offset, BufRead, Read, ReadError, Seek, SeekError, SeekFrom};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::convert::TryInto;
+use core::mem;
+use core::str;
+
+const JOURNAL_SLOTS: usize = 2;
+
+#[derive(Debug)]
+pub enum FsError {
+    #[allow(dead_code)]
+    Media(SaveError),
+    NotFound,
+    AlreadyExists,
+    BadName,
+    NoSpace,
+    Corrupt,
+}
+
+impl From<SaveError> for FsError {
+    fn from(error: SaveError) -> Self {
+        FsError::Media(error)
+    }
+}
+
+impl From<SlotError> for FsError {
+    fn from(error: SlotError) -> Self {
+        match error {
+            SlotError::Media(error) => FsError::Media(error),
+            SlotError::TooLarge => FsError::NoSpace,
+            _ => FsError::Corrupt,
+        }
+    }
+}
+
+struct SaveEntry {
+    name: String,
+    data: Vec<u8>,
+}
+
+// Names are stored normalized so any spelling of a path finds the same file
+fn normalize(path: &str) -> Result<String, FsError> {
+    if path.ends_with('/') {
+        return Err(FsError::BadName);
+    }
+    let path = normalize_path(path);
+    if path.is_empty() || path.len() > 255 {
+        Err(FsError::BadName)
+    } else {
+        Ok(path)
+    }
+}
+
+// The whole filesystem lives in ram and every change commits a new image through the journal,
+// so a file is either all old or all new after a power loss. The image is a u16 file count, then
+// for each file a u8 name length, the name, a u32 data length and the data.
+pub struct SaveFs<M> {
+    journal: Journal<M>,
+    files: Vec<SaveEntry>,
+}
+
+#[allow(dead_code)]
+impl<M: SaveMedia> SaveFs<M> {
+    pub fn mount(media: M) -> Result<Self, FsError> {
+        let mut journal = Journal::open(media, JOURNAL_SLOTS)?;
+        let files = match journal.read()? {
+            Some(image) => Self::parse_image(&image).ok_or(FsError::Corrupt)?,
+            None => Vec::new(),
+        };
+        Ok(SaveFs { journal, files })
+    }
+
+    fn parse_image(image: &[u8]) -> Option<Vec<SaveEntry>> {
+        let count = u16::from_le_bytes(image.get(0..2)?.try_into().unwrap());
+        let mut files = Vec::with_capacity(usize::from(count));
+        let mut offset = 2;
+        for _ in 0..count {
+            let name_len = usize::from(*image.get(offset)?);
+            let name = str::from_utf8(image.get(offset + 1..offset + 1 + name_len)?).ok()?;
+            offset += 1 + name_len;
+            let data_len =
+                u32::from_le_bytes(image.get(offset..offset + 4)?.try_into().unwrap()) as usize;
+            offset += 4;
+            let data = image.get(offset..offset + data_len)?;
+            offset += data_len;
+            files.push(SaveEntry {
+                name: String::from(name),
+                data: data.to_vec(),
+            });
+        }
+        Some(files)
+    }
+
+    fn commit(&mut self) -> Result<(), FsError> {
+        let mut image = Vec::new();
+        image.extend_from_slice(&(self.files.len() as u16).to_le_bytes());
+        for file in self.files.iter() {
+            image.push(file.name.len() as u8);
+            image.extend_from_slice(file.name.as_bytes());
+            image.extend_from_slice(&(file.data.len() as u32).to_le_bytes());
+            image.extend_from_slice(&file.data);
+        }
+        self.journal.commit(&image)?;
+        Ok(())
+    }
+
+    fn find(&self, path: &str) -> Option<usize> {
+        let path = normalize(path).ok()?;
+        self.files.iter().position(|file| file.name == path)
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|file| file.name.as_str())
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    pub fn read(&self, path: &str) -> Option<&[u8]> {
+        self.find(path)
+            .map(|index| self.files[index].data.as_slice())
+    }
+
+    pub fn create(&mut self, path: &str) -> Result<(), FsError> {
+        if self.exists(path) {
+            return Err(FsError::AlreadyExists);
+        }
+        self.write(path, &[])
+    }
+
+    // Replaces the contents of a file, creating it if needed
+    pub fn write(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        let name = normalize(path)?;
+        match self.find(&name) {
+            Some(index) => {
+                let old = mem::replace(&mut self.files[index].data, data.to_vec());
+                if let Err(error) = self.commit() {
+                    self.files[index].data = old;
+                    return Err(error);
+                }
+                Ok(())
+            }
+            None => {
+                self.files.push(SaveEntry {
+                    name,
+                    data: data.to_vec(),
+                });
+                if let Err(error) = self.commit() {
+                    self.files.pop();
+                    return Err(error);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn delete(&mut self, path: &str) -> Result<(), FsError> {
+        let index = self.find(path).ok_or(FsError::NotFound)?;
+        let file = self.files.remove(index);
+        if let Err(error) = self.commit() {
+            self.files.insert(index, file);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+        let to = normalize(to)?;
+        let index = self.find(from).ok_or(FsError::NotFound)?;
+        if self.exists(&to) {
+            return Err(FsError::AlreadyExists);
+        }
+        let old = mem::replace(&mut self.files[index].name, to);
+        if let Err(error) = self.commit() {
+            self.files[index].name = old;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    pub fn into_media(self) -> M {
+        self.journal.into_media()
+    }
+}
+
+pub struct SaveFile<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+pub enum File<'a> {
+    Rom(RomFile),
+    Save(SaveFile<'a>),
+}
+
+impl<'a> SaveFile<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        &self.data[self.offset..]
+    }
+}
+
+impl<'a> Read for SaveFile<'a> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        let count = min(self.data.len() - self.offset, buffer.len());
+        buffer[..count].copy_from_slice(&self.data[self.offset..self.offset + count]);
+        self.offset += count;
+        Ok(count)
+    }
+}
+
+impl<'a> BufRead for SaveFile<'a> {
+    fn fill_buf(&mut self) -> Result<&[u8], ReadError> {
+        Ok(&self.data[self.offset..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.offset = min(self.offset + amount, self.data.len());
+    }
+}
+
+impl<'a> Seek for SaveFile<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u32, SeekError> {
+        self.offset = seek_offset(self.data.len(), self.offset, pos)?;
+        Ok(self.offset as u32)
+    }
+}
+
+#[allow(dead_code)]
+impl<'a> File<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        match self {
+            File::Rom(file) => file.as_bytes(),
+            File::Save(file) => file.as_bytes(),
+        }
+    }
+
+    pub fn is_save(&self) -> bool {
+        matches!(self, File::Save(_))
+    }
+}
+
+impl<'a> Read for File<'a> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        match self {
+            File::Rom(file) => file.read(buffer),
+            File::Save(file) => file.read(buffer),
+        }
+    }
+}
+
+impl<'a> BufRead for File<'a> {
+    fn fill_buf(&mut self) -> Result<&[u8], ReadError> {
+        match self {
+            File::Rom(file) => file.fill_buf(),
+            File::Save(file) => file.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            File::Rom(file) => file.consume(amount),
+            File::Save(file) => file.consume(amount),
+        }
+    }
+}
+
+impl<'a> Seek for File<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u32, SeekError> {
+        match self {
+            File::Rom(file) => file.seek(pos),
+            File::Save(file) => file.seek(pos),
+        }
+    }
+}
+
+// Save files shadow rom files with the same path
+pub struct OverlayFs<M> {
+    save: SaveFs<M>,
+}
+
+#[allow(dead_code)]
+impl<M: SaveMedia> OverlayFs<M> {
+    pub fn new(save: SaveFs<M>) -> Self {
+        OverlayFs { save }
+    }
+
+    pub fn open(&self, path: &str) -> Result<File<'_>, OpenError> {
+        match self.save.read(path) {
+            Some(data) => Ok(File::Save(SaveFile { data, offset: 0 })),
+            None => RomFile::open(path).map(File::Rom),
+        }
+    }
+
+    pub fn save(&self) -> &SaveFs<M> {
+        &self.save
+    }
+
+    pub fn save_mut(&mut self) -> &mut SaveFs<M> {
+        &mut self.save
+    }
+
+    pub fn into_save(self) -> SaveFs<M> {
+        self.save
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save::MemorySave;
+
+    #[test]
+    fn path_spellings() {
+        let mut save = SaveFs::mount(MemorySave::new(1024)).unwrap();
+        save.write("/dir//./high.sav", b"1").unwrap();
+        assert_eq!(save.list().collect::<Vec<_>>(), ["dir/high.sav"]);
+        assert_eq!(save.read("dir/high.sav"), Some(&b"1"[..]));
+        assert_eq!(save.read("dir/sub/../high.sav"), Some(&b"1"[..]));
+        assert!(matches!(
+            save.create("./dir/high.sav"),
+            Err(FsError::AlreadyExists)
+        ));
+
+        save.rename("dir/high.sav", "/high.sav").unwrap();
+        assert!(!save.exists("dir/high.sav"));
+        let save = SaveFs::mount(save.into_media()).unwrap();
+        assert_eq!(save.read("high.sav"), Some(&b"1"[..]));
+    }
+
+    #[test]
+    fn bad_names() {
+        let mut save = SaveFs::mount(MemorySave::new(1024)).unwrap();
+        for name in ["", "/", "dir/..", "high.sav/", "./."].iter() {
+            assert!(matches!(save.write(name, b"1"), Err(FsError::BadName)));
+        }
+        let long = "a/".repeat(127) + "ab";
+        assert!(matches!(save.write(&long, b"1"), Err(FsError::BadName)));
+        assert_eq!(save.list().count(), 0);
+    }
+}
@@ -0,0 +1,289 @@
+use super::{crc32, SaveError, SaveMedia, SlotError};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+const MAGIC: [u8; 4] = *b"JRNL";
+
+// magic, u32 sequence, u32 length, u32 crc32 of the data, u32 crc32 of the header before it
+const HEADER_SIZE: usize = 20;
+
+struct Header {
+    sequence: u32,
+    len: usize,
+    crc: u32,
+}
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[8..12].copy_from_slice(&(self.len as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        let header_crc = crc32(&bytes[..16]);
+        bytes[16..20].copy_from_slice(&header_crc.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Option<Self> {
+        let field =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        if bytes[0..4] != MAGIC || crc32(&bytes[..16]) != field(16) {
+            return None;
+        }
+        Some(Header {
+            sequence: field(4),
+            len: field(8) as usize,
+            crc: field(12),
+        })
+    }
+}
+
+// Sequence numbers wrap, so newer means less than half the range ahead
+fn is_newer(sequence: u32, than: u32) -> bool {
+    sequence.wrapping_sub(than) as i32 > 0
+}
+
+// Keeps the last committed data in one of several slots, writing each commit to the slot after
+// the newest one. The data goes down before the header that makes it valid, so losing power
+// during a commit leaves the previous one in place.
+pub struct Journal<M> {
+    media: M,
+    slots: usize,
+    slot_size: usize,
+    newest: Option<(usize, Header)>,
+}
+
+#[allow(dead_code)]
+impl<M: SaveMedia> Journal<M> {
+    // Slots are rounded down to the media's block size so commits never share a block
+    pub fn open(mut media: M, slots: usize) -> Result<Self, SaveError> {
+        assert!(slots >= 2, "Journal needs at least two slots");
+        let block_size = media.block_size();
+        let slot_size = media.len() / slots / block_size * block_size;
+        assert!(
+            slot_size > HEADER_SIZE,
+            "Save media is too small for the journal"
+        );
+
+        let mut newest: Option<(usize, Header)> = None;
+        let mut data = Vec::new();
+        for slot in 0..slots {
+            let mut bytes = [0; HEADER_SIZE];
+            media.read(slot * slot_size, &mut bytes)?;
+            let header = match Header::from_bytes(&bytes) {
+                Some(header) if header.len <= slot_size - HEADER_SIZE => header,
+                _ => continue,
+            };
+            if let Some((_, current)) = &newest {
+                if !is_newer(header.sequence, current.sequence) {
+                    continue;
+                }
+            }
+
+            data.resize(header.len, 0);
+            media.read(slot * slot_size + HEADER_SIZE, &mut data)?;
+            if crc32(&data) == header.crc {
+                newest = Some((slot, header));
+            }
+        }
+
+        Ok(Journal {
+            media,
+            slots,
+            slot_size,
+            newest,
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slot_size - HEADER_SIZE
+    }
+
+    // Sequence number of the newest commit
+    pub fn sequence(&self) -> Option<u32> {
+        self.newest.as_ref().map(|(_, header)| header.sequence)
+    }
+
+    pub fn into_media(self) -> M {
+        self.media
+    }
+
+    pub fn read(&mut self) -> Result<Option<Vec<u8>>, SaveError> {
+        let (slot, header) = match &self.newest {
+            Some(newest) => newest,
+            None => return Ok(None),
+        };
+        let mut data = vec![0; header.len];
+        self.media
+            .read(slot * self.slot_size + HEADER_SIZE, &mut data)?;
+        Ok(Some(data))
+    }
+
+    pub fn commit(&mut self, data: &[u8]) -> Result<(), SlotError> {
+        if data.len() > self.capacity() {
+            return Err(SlotError::TooLarge);
+        }
+        let (slot, sequence) = match &self.newest {
+            Some((slot, header)) => ((slot + 1) % self.slots, header.sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let header = Header {
+            sequence,
+            len: data.len(),
+            crc: crc32(data),
+        };
+
+        let offset = slot * self.slot_size;
+        self.media.write(offset + HEADER_SIZE, data)?;
+        self.media.write(offset, &header.to_bytes())?;
+        self.newest = Some((slot, header));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MemorySave;
+    use super::*;
+
+    // Cuts the power at write `writes_left`, which either doesn't happen or only half lands
+    struct PowerCut {
+        media: MemorySave,
+        writes_left: usize,
+        torn: bool,
+    }
+
+    impl SaveMedia for PowerCut {
+        fn len(&self) -> usize {
+            self.media.len()
+        }
+
+        fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), SaveError> {
+            self.media.read(offset, buffer)
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError> {
+            if self.writes_left == 0 {
+                if self.torn {
+                    self.media.write(offset, &data[..data.len() / 2])?;
+                    self.torn = false;
+                }
+                return Err(SaveError::Timeout);
+            }
+            self.writes_left -= 1;
+            self.media.write(offset, data)
+        }
+    }
+
+    const MEDIA_SIZE: usize = 256;
+
+    fn payloads() -> Vec<Vec<u8>> {
+        (0..7u8)
+            .map(|commit| {
+                (0..usize::from(commit) * 5 + 1)
+                    .map(|i| commit ^ i as u8)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn committed(slots: usize, payloads: &[Vec<u8>]) -> MemorySave {
+        let mut media = MemorySave::new(MEDIA_SIZE);
+        let mut journal = Journal::open(&mut media, slots).unwrap();
+        for payload in payloads {
+            journal.commit(payload).unwrap();
+        }
+        media
+    }
+
+    #[test]
+    fn power_cut_at_every_write() {
+        let payloads = payloads();
+        for slots in 2..=4 {
+            for commit in 0..payloads.len() {
+                let mut counter = PowerCut {
+                    media: committed(slots, &payloads[..commit]),
+                    writes_left: usize::MAX,
+                    torn: false,
+                };
+                let mut journal = Journal::open(&mut counter, slots).unwrap();
+                journal.commit(&payloads[commit]).unwrap();
+                let writes = usize::MAX - counter.writes_left;
+
+                for cut in 0..=writes {
+                    for torn in [false, true].iter() {
+                        let mut media = PowerCut {
+                            media: committed(slots, &payloads[..commit]),
+                            writes_left: cut,
+                            torn: *torn,
+                        };
+                        let mut journal = Journal::open(&mut media, slots).unwrap();
+                        let result = journal.commit(&payloads[commit]);
+                        assert_eq!(result.is_ok(), cut == writes);
+
+                        let expected = if cut == writes {
+                            Some(&payloads[commit])
+                        } else {
+                            commit.checked_sub(1).map(|last| &payloads[last])
+                        };
+                        let mut journal = Journal::open(&mut media.media, slots).unwrap();
+                        assert_eq!(journal.read().unwrap().as_ref(), expected);
+
+                        // The next boot can commit again
+                        journal.commit(&payloads[commit]).unwrap();
+                        let mut journal = Journal::open(&mut media.media, slots).unwrap();
+                        assert_eq!(journal.read().unwrap().as_ref(), Some(&payloads[commit]));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_and_too_large() {
+        let mut media = MemorySave::new(MEDIA_SIZE);
+        let mut journal = Journal::open(&mut media, 2).unwrap();
+        assert_eq!(journal.read().unwrap(), None);
+        assert_eq!(journal.sequence(), None);
+        assert_eq!(journal.capacity(), MEDIA_SIZE / 2 - HEADER_SIZE);
+
+        let big = vec![0; journal.capacity() + 1];
+        assert!(matches!(journal.commit(&big), Err(SlotError::TooLarge)));
+        assert!(media.as_bytes().iter().all(|byte| *byte == 0xFF));
+    }
+
+    #[test]
+    fn sequence_wrap() {
+        assert!(is_newer(0, u32::MAX));
+        assert!(is_newer(1, u32::MAX - 1));
+        assert!(!is_newer(u32::MAX, 0));
+        assert!(!is_newer(7, 7));
+
+        let mut media = MemorySave::new(MEDIA_SIZE);
+        let slot_size = MEDIA_SIZE / 2;
+        for (slot, (sequence, data)) in [(0, b"NEW"), (u32::MAX, b"OLD")].iter().enumerate() {
+            let header = Header {
+                sequence: *sequence,
+                len: data.len(),
+                crc: crc32(*data),
+            };
+            media.write(slot * slot_size, &header.to_bytes()).unwrap();
+            media.write(slot * slot_size + HEADER_SIZE, *data).unwrap();
+        }
+
+        let mut journal = Journal::open(&mut media, 2).unwrap();
+        assert_eq!(journal.sequence(), Some(0));
+        assert_eq!(journal.read().unwrap().unwrap(), b"NEW");
+
+        // The next commit replaces the old slot, not the newest one
+        journal.commit(b"NEXT").unwrap();
+        let mut journal = Journal::open(&mut media, 2).unwrap();
+        assert_eq!(journal.sequence(), Some(1));
+        assert_eq!(journal.read().unwrap().unwrap(), b"NEXT");
+        let mut old = [0; 3];
+        media.read(HEADER_SIZE, &mut old).unwrap();
+        assert_eq!(&old, b"NEW");
+    }
+}
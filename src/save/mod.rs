@@ -0,0 +1,417 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::ptr;
+
+mod eeprom;
+mod flash;
+mod fs;
+mod journal;
+mod sram;
+#[allow(unused_imports)]
+pub use eeprom::{Eeprom, EepromSize};
+#[allow(unused_imports)]
+pub use flash::{Flash, FlashChip};
+#[allow(unused_imports)]
+pub use fs::{File, FsError, OverlayFs, SaveFile, SaveFs};
+pub use journal::Journal;
+#[allow(unused_imports)]
+pub use sram::Sram;
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum SaveError {
+    OutOfBounds,
+    Timeout,
+    #[allow(dead_code)]
+    UnknownDevice(u16),
+}
+
+pub trait SaveMedia {
+    fn len(&self) -> usize;
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), SaveError>;
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError>;
+
+    // Writes smaller than this rewrite the whole block around them
+    fn block_size(&self) -> usize {
+        1
+    }
+
+    #[allow(dead_code)]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<M: SaveMedia + ?Sized> SaveMedia for &mut M {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), SaveError> {
+        (**self).read(offset, buffer)
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError> {
+        (**self).write(offset, data)
+    }
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+}
+
+pub(crate) fn check_bounds(media_len: usize, offset: usize, len: usize) -> Result<(), SaveError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= media_len => Ok(()),
+        _ => Err(SaveError::OutOfBounds),
+    }
+}
+
+fn set_save_wait() {
+    unsafe {
+        let wait_control = ptr::read_volatile(0x4000204 as *const u16);
+        ptr::write_volatile(0x4000204 as *mut u16, wait_control | 3); // 8 cycle sram wait
+    }
+}
+
+// Save media kept in ram, for running save code without a cartridge
+pub struct MemorySave {
+    data: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl MemorySave {
+    pub fn new(len: usize) -> Self {
+        MemorySave {
+            data: vec![0xFF; len],
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl SaveMedia for MemorySave {
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), SaveError> {
+        check_bounds(self.data.len(), offset, buffer.len())?;
+        buffer.copy_from_slice(&self.data[offset..offset + buffer.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError> {
+        check_bounds(self.data.len(), offset, data.len())?;
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+static CRC32_NIBBLE_TABLE: [u32; 16] = [
+    0x00000000, 0x1DB71064, 0x3B6E20C8, 0x26D930AC, 0x76DC4190, 0x6B6B51F4, 0x4DB26158, 0x5005713C,
+    0xEDB88320, 0xF00F9344, 0xD6D6A3E8, 0xCB61B38C, 0x9B64C2B0, 0x86D3D2D4, 0xA00AE278, 0xBDBDF21C,
+];
+
+// Standard crc32 as used by zip and png
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        crc = (crc >> 4) ^ CRC32_NIBBLE_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) ^ CRC32_NIBBLE_TABLE[(crc & 0xF) as usize];
+    }
+    !crc
+}
+
+#[allow(dead_code)]
+pub trait SaveData: Sized {
+    const MAGIC: [u8; 4];
+    const VERSION: u16;
+
+    fn serialize(&self, out: &mut Vec<u8>);
+
+    // Version is the one the data was saved with, for migrating old saves
+    fn deserialize(version: u16, data: &[u8]) -> Option<Self>;
+}
+
+#[derive(Debug)]
+pub enum SlotError {
+    Media(SaveError),
+    Empty,
+    WrongMagic,
+    #[allow(dead_code)]
+    NewerVersion(u16),
+    BadChecksum,
+    TooLarge,
+    Corrupt,
+}
+
+impl From<SaveError> for SlotError {
+    fn from(error: SaveError) -> Self {
+        SlotError::Media(error)
+    }
+}
+
+// magic, u16 version, u16 reserved, u32 length, u32 crc32 of the data
+const SLOT_HEADER_SIZE: usize = 16;
+
+pub struct SaveSlot<M> {
+    media: M,
+    offset: usize,
+    capacity: usize,
+}
+
+#[allow(dead_code)]
+impl<M: SaveMedia> SaveSlot<M> {
+    pub fn new(media: M, offset: usize, capacity: usize) -> Self {
+        assert!(capacity > SLOT_HEADER_SIZE, "Save slot is too small");
+        assert!(
+            offset + capacity <= media.len(),
+            "Save slot is past the end of save media"
+        );
+        SaveSlot {
+            media,
+            offset,
+            capacity,
+        }
+    }
+
+    pub fn into_media(self) -> M {
+        self.media
+    }
+
+    pub fn load<T: SaveData>(&mut self) -> Result<T, SlotError> {
+        let mut header = [0; SLOT_HEADER_SIZE];
+        self.media.read(self.offset, &mut header)?;
+        if header[0..4] == [0xFF; 4] || header[0..4] == [0; 4] {
+            return Err(SlotError::Empty);
+        }
+        if header[0..4] != T::MAGIC {
+            return Err(SlotError::WrongMagic);
+        }
+        let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        if version > T::VERSION {
+            return Err(SlotError::NewerVersion(version));
+        }
+        let len = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        if len > self.capacity - SLOT_HEADER_SIZE {
+            return Err(SlotError::Corrupt);
+        }
+
+        let mut data = vec![0; len];
+        self.media.read(self.offset + SLOT_HEADER_SIZE, &mut data)?;
+        if crc32(&data) != crc {
+            return Err(SlotError::BadChecksum);
+        }
+        T::deserialize(version, &data).ok_or(SlotError::Corrupt)
+    }
+
+    pub fn store<T: SaveData>(&mut self, value: &T) -> Result<(), SlotError> {
+        let mut data = Vec::new();
+        value.serialize(&mut data);
+        if data.len() > self.capacity - SLOT_HEADER_SIZE {
+            return Err(SlotError::TooLarge);
+        }
+
+        let mut header = [0; SLOT_HEADER_SIZE];
+        header[0..4].copy_from_slice(&T::MAGIC);
+        header[4..6].copy_from_slice(&T::VERSION.to_le_bytes());
+        header[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&crc32(&data).to_le_bytes());
+
+        self.media.write(self.offset + SLOT_HEADER_SIZE, &data)?;
+        self.media.write(self.offset, &header)?;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<(), SlotError> {
+        self.media.write(self.offset, &[0xFF; SLOT_HEADER_SIZE])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Settings {
+        volume: u8,
+        name: Vec<u8>,
+    }
+
+    impl SaveData for Settings {
+        const MAGIC: [u8; 4] = *b"SETT";
+        const VERSION: u16 = 2;
+
+        fn serialize(&self, out: &mut Vec<u8>) {
+            out.push(self.volume);
+            out.extend_from_slice(&self.name);
+        }
+
+        fn deserialize(version: u16, data: &[u8]) -> Option<Self> {
+            // Version 1 had no volume
+            match version {
+                1 => Some(Settings {
+                    volume: 64,
+                    name: data.to_vec(),
+                }),
+                _ => Some(Settings {
+                    volume: *data.first()?,
+                    name: data[1..].to_vec(),
+                }),
+            }
+        }
+    }
+
+    struct SettingsV1(Vec<u8>);
+
+    impl SaveData for SettingsV1 {
+        const MAGIC: [u8; 4] = Settings::MAGIC;
+        const VERSION: u16 = 1;
+
+        fn serialize(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(&self.0);
+        }
+
+        fn deserialize(_: u16, data: &[u8]) -> Option<Self> {
+            Some(SettingsV1(data.to_vec()))
+        }
+    }
+
+    struct SettingsV3;
+
+    impl SaveData for SettingsV3 {
+        const MAGIC: [u8; 4] = Settings::MAGIC;
+        const VERSION: u16 = 3;
+
+        fn serialize(&self, out: &mut Vec<u8>) {
+            out.push(0);
+        }
+
+        fn deserialize(_: u16, _: &[u8]) -> Option<Self> {
+            Some(SettingsV3)
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            volume: 40,
+            name: b"KATE".to_vec(),
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut media = MemorySave::new(256);
+        let mut first = SaveSlot::new(&mut media, 0, 128);
+        assert!(matches!(first.load::<Settings>(), Err(SlotError::Empty)));
+        first.store(&settings()).unwrap();
+        assert_eq!(first.load::<Settings>().unwrap(), settings());
+
+        let mut second = SaveSlot::new(&mut media, 128, 128);
+        assert!(matches!(second.load::<Settings>(), Err(SlotError::Empty)));
+        second
+            .store(&Settings {
+                volume: 0,
+                name: Vec::new(),
+            })
+            .unwrap();
+        let mut first = SaveSlot::new(&mut media, 0, 128);
+        assert_eq!(first.load::<Settings>().unwrap(), settings());
+
+        first.clear().unwrap();
+        assert!(matches!(first.load::<Settings>(), Err(SlotError::Empty)));
+    }
+
+    #[test]
+    fn flipped_byte() {
+        let mut media = MemorySave::new(64);
+        SaveSlot::new(&mut media, 0, 64).store(&settings()).unwrap();
+        let mut byte = [0];
+        media.read(SLOT_HEADER_SIZE + 2, &mut byte).unwrap();
+        media
+            .write(SLOT_HEADER_SIZE + 2, &[byte[0] ^ 0x10])
+            .unwrap();
+        let loaded = SaveSlot::new(&mut media, 0, 64).load::<Settings>();
+        assert!(matches!(loaded, Err(SlotError::BadChecksum)));
+    }
+
+    #[test]
+    fn versions() {
+        let mut media = MemorySave::new(64);
+        let mut slot = SaveSlot::new(&mut media, 0, 64);
+        slot.store(&SettingsV1(b"OLD".to_vec())).unwrap();
+        let migrated = slot.load::<Settings>().unwrap();
+        assert_eq!(migrated.volume, 64);
+        assert_eq!(migrated.name, b"OLD");
+
+        slot.store(&SettingsV3).unwrap();
+        assert!(matches!(
+            slot.load::<Settings>(),
+            Err(SlotError::NewerVersion(3))
+        ));
+    }
+
+    #[test]
+    fn wrong_magic() {
+        struct Other;
+
+        impl SaveData for Other {
+            const MAGIC: [u8; 4] = *b"OTHR";
+            const VERSION: u16 = 1;
+
+            fn serialize(&self, _: &mut Vec<u8>) {}
+
+            fn deserialize(_: u16, _: &[u8]) -> Option<Self> {
+                Some(Other)
+            }
+        }
+
+        let mut media = MemorySave::new(64);
+        let mut slot = SaveSlot::new(&mut media, 0, 64);
+        slot.store(&Other).unwrap();
+        assert!(matches!(
+            slot.load::<Settings>(),
+            Err(SlotError::WrongMagic)
+        ));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut media = MemorySave::new(64);
+        let mut slot = SaveSlot::new(&mut media, 0, 32);
+        let big = Settings {
+            volume: 1,
+            name: vec![b'A'; 32 - SLOT_HEADER_SIZE],
+        };
+        assert!(matches!(slot.store(&big), Err(SlotError::TooLarge)));
+        assert!(matches!(slot.load::<Settings>(), Err(SlotError::Empty)));
+        assert!(media.as_bytes().iter().all(|byte| *byte == 0xFF));
+
+        let fits = Settings {
+            volume: 1,
+            name: vec![b'A'; 32 - SLOT_HEADER_SIZE - 1],
+        };
+        let mut slot = SaveSlot::new(&mut media, 0, 32);
+        slot.store(&fits).unwrap();
+        assert_eq!(slot.load::<Settings>().unwrap(), fits);
+    }
+}
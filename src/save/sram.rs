@@ -0,0 +1,57 @@
+use super::{check_bounds, set_save_wait, SaveError, SaveMedia};
+use core::marker::PhantomData;
+use core::ptr;
+
+pub(super) const SRAM_BASE: usize = 0xE000000;
+const SRAM_SIZE: usize = 0x8000;
+
+// Sram is on an 8 bit bus, so it is only ever accessed a byte at a time
+#[link_section = ".fast_text"]
+pub(super) fn sram_read(offset: usize, buffer: &mut [u8]) {
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = unsafe { ptr::read_volatile((SRAM_BASE + offset + i) as *const u8) };
+    }
+}
+
+#[link_section = ".fast_text"]
+fn sram_write(offset: usize, data: &[u8]) {
+    for (i, byte) in data.iter().enumerate() {
+        unsafe { ptr::write_volatile((SRAM_BASE + offset + i) as *mut u8, *byte) };
+    }
+}
+
+pub struct Sram {
+    _priv: PhantomData<*mut ()>,
+}
+
+#[allow(dead_code)]
+impl Sram {
+    pub fn new() -> Self {
+        set_save_wait();
+        Sram { _priv: PhantomData }
+    }
+}
+
+impl Default for Sram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SaveMedia for Sram {
+    fn len(&self) -> usize {
+        SRAM_SIZE
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), SaveError> {
+        check_bounds(SRAM_SIZE, offset, buffer.len())?;
+        sram_read(offset, buffer);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError> {
+        check_bounds(SRAM_SIZE, offset, data.len())?;
+        sram_write(offset, data);
+        Ok(())
+    }
+}
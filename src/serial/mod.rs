@@ -0,0 +1,165 @@
+use crate::util::without_irq;
+use crate::volatile::{VolatileCell, VolatileUsize};
+use core::cell::UnsafeCell;
+use core::ptr;
+
+mod multiplay;
+mod normal;
+mod uart;
+pub use multiplay::Multiplay;
+#[allow(unused_imports)]
+pub use normal::{Clock, Normal, NormalWidth};
+#[allow(unused_imports)]
+pub use uart::Uart;
+
+const QUEUE_LEN: usize = 32;
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub enum Baud {
+    B9600,
+    B38400,
+    B57600,
+    B115200,
+}
+
+impl Baud {
+    fn bits(self) -> u16 {
+        match self {
+            Baud::B9600 => 0,
+            Baud::B38400 => 1,
+            Baud::B57600 => 2,
+            Baud::B115200 => 3,
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum SerialError {
+    InUse,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Off,
+    Normal { master: bool, wide: bool },
+    Multiplay,
+    Uart,
+}
+
+// Single producer, single consumer queue shared with the serial irq. Multiplay packs the four
+// player halfwords into one entry, which is why entries are u64.
+struct Queue {
+    data: UnsafeCell<[u64; QUEUE_LEN]>,
+    head: VolatileUsize,
+    tail: VolatileUsize,
+}
+
+unsafe impl Sync for Queue {}
+
+impl Queue {
+    const fn new() -> Self {
+        Queue {
+            data: UnsafeCell::new([0; QUEUE_LEN]),
+            head: VolatileUsize::new(0),
+            tail: VolatileUsize::new(0),
+        }
+    }
+
+    fn push(&self, value: u64) -> bool {
+        let tail = self.tail.read();
+        let next = (tail + 1) % QUEUE_LEN;
+        if next == self.head.read() {
+            return false;
+        }
+        unsafe { ptr::write_volatile((self.data.get() as *mut u64).add(tail), value) }
+        self.tail.write(next);
+        true
+    }
+
+    fn pop(&self) -> Option<u64> {
+        let head = self.head.read();
+        if head == self.tail.read() {
+            return None;
+        }
+        let value = unsafe { ptr::read_volatile((self.data.get() as *const u64).add(head)) };
+        self.head.write((head + 1) % QUEUE_LEN);
+        Some(value)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.read() == self.tail.read()
+    }
+
+    // Only safe with the irq off
+    fn clear(&self) {
+        self.head.write(0);
+        self.tail.write(0);
+    }
+}
+
+static MODE: VolatileCell<Mode> = VolatileCell::new(Mode::Off);
+static RECEIVED: Queue = Queue::new();
+static TO_SEND: Queue = Queue::new();
+static DROPPED: VolatileUsize = VolatileUsize::new(0); // Received while RECEIVED was full
+static ERRORS: VolatileUsize = VolatileUsize::new(0);
+
+fn claim(mode: Mode) -> Result<(), SerialError> {
+    without_irq(|| {
+        if MODE.read() != Mode::Off {
+            return Err(SerialError::InUse);
+        }
+        RECEIVED.clear();
+        TO_SEND.clear();
+        DROPPED.write(0);
+        ERRORS.write(0);
+        MODE.write(mode);
+        Ok(())
+    })
+}
+
+fn enable_irq() {
+    unsafe {
+        let enabled = ptr::read_volatile(0x4000200 as *const u16);
+        ptr::write_volatile(0x4000200 as *mut u16, enabled | 0x80); // Turn on serial irq
+    }
+}
+
+fn release() {
+    unsafe {
+        let enabled = ptr::read_volatile(0x4000200 as *const u16);
+        ptr::write_volatile(0x4000200 as *mut u16, enabled & !0x80);
+        ptr::write_volatile(0x4000128 as *mut u16, 0); // Stop the port
+    }
+    MODE.write(Mode::Off);
+}
+
+// Entries the irq couldn't queue because nothing was receiving them
+#[allow(dead_code)]
+pub fn dropped() -> usize {
+    DROPPED.read()
+}
+
+// Parity, framing or multiplay errors seen by the irq
+#[allow(dead_code)]
+pub fn errors() -> usize {
+    ERRORS.read()
+}
+
+fn received(value: u64) {
+    if !RECEIVED.push(value) {
+        DROPPED.update(|dropped| dropped + 1);
+    }
+}
+
+#[no_mangle]
+#[link_section = ".fast_text"]
+extern "C" fn serial_irq() {
+    match MODE.read() {
+        Mode::Off => {}
+        Mode::Normal { master, wide } => normal::handle_irq(master, wide),
+        Mode::Multiplay => multiplay::handle_irq(),
+        Mode::Uart => uart::handle_irq(),
+    }
+}
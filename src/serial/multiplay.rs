@@ -0,0 +1,132 @@
+use super::{
+    claim, enable_irq, received, release, Baud, Mode, SerialError, ERRORS, RECEIVED, TO_SEND,
+};
+use crate::util::without_irq;
+use crate::volatile::VolatileBool;
+use core::ptr;
+
+// Set while a value is loaded and not yet sent
+static LOADED: VolatileBool = VolatileBool::new(false);
+// The player id is only set by the first transfer
+static TRANSFERRED: VolatileBool = VolatileBool::new(false);
+
+fn is_parent() -> bool {
+    unsafe { ptr::read_volatile(0x4000128 as *const u16) & 0x4 == 0 } // Si is low on the parent
+}
+
+fn all_ready() -> bool {
+    unsafe { ptr::read_volatile(0x4000128 as *const u16) & 0x8 != 0 }
+}
+
+// Children load their next value and wait, the parent also starts the transfer once every unit
+// is ready
+fn start_transfer() {
+    if LOADED.read() {
+        return;
+    }
+    let parent = is_parent();
+    if parent && !all_ready() {
+        return;
+    }
+    let value = match TO_SEND.pop() {
+        Some(value) => value as u16,
+        None => return,
+    };
+    unsafe {
+        ptr::write_volatile(0x400012A as *mut u16, value); // Siomlt_send
+        if parent {
+            let control = ptr::read_volatile(0x4000128 as *const u16);
+            ptr::write_volatile(0x4000128 as *mut u16, control | 0x80); // Start
+        }
+    }
+    LOADED.write(true);
+}
+
+#[link_section = ".fast_text"]
+pub(super) fn handle_irq() {
+    unsafe {
+        if ptr::read_volatile(0x4000128 as *const u16) & 0x40 != 0 {
+            ERRORS.update(|errors| errors + 1);
+        }
+        let mut frame = 0;
+        for player in 0..4 {
+            let data = ptr::read_volatile((0x4000120 + player * 2) as *const u16); // Siomulti
+            frame |= u64::from(data) << (player * 16);
+        }
+        received(frame);
+    }
+    LOADED.write(false);
+    TRANSFERRED.write(true);
+    start_transfer();
+}
+
+// Up to four units, each transfer hands every unit the halfword each player sent. Players that
+// aren't connected read as 0xFFFF.
+pub struct Multiplay {
+    _priv: (),
+}
+
+#[allow(dead_code)]
+impl Multiplay {
+    pub fn new(baud: Baud) -> Result<Self, SerialError> {
+        claim(Mode::Multiplay)?;
+        LOADED.write(false);
+        TRANSFERRED.write(false);
+        unsafe {
+            ptr::write_volatile(0x4000134 as *mut u16, 0); // Serial mode in rcnt
+            let control = baud.bits() | 0x2000 | 0x4000; // Multiplay with irq
+            ptr::write_volatile(0x4000128 as *mut u16, control);
+        }
+        enable_irq();
+        Ok(Multiplay { _priv: () })
+    }
+
+    pub fn is_parent(&self) -> bool {
+        is_parent()
+    }
+
+    pub fn all_ready(&self) -> bool {
+        all_ready()
+    }
+
+    // None until the first transfer is done
+    pub fn player_id(&self) -> Option<u8> {
+        if !TRANSFERRED.read() {
+            return None;
+        }
+        unsafe { Some((ptr::read_volatile(0x4000128 as *const u16) >> 4) as u8 & 3) }
+    }
+
+    // Returns false if the send queue is full
+    pub fn send(&mut self, value: u16) -> bool {
+        if !TO_SEND.push(u64::from(value)) {
+            return false;
+        }
+        without_irq(start_transfer);
+        true
+    }
+
+    // Everything sent so far has been transferred
+    pub fn is_idle(&self) -> bool {
+        TO_SEND.is_empty() && !LOADED.read()
+    }
+
+    // Also retries a parent transfer that was waiting for the other units to be ready
+    pub fn receive(&mut self) -> Option<[u16; 4]> {
+        without_irq(start_transfer);
+        RECEIVED.pop().map(|frame| {
+            [
+                frame as u16,
+                (frame >> 16) as u16,
+                (frame >> 32) as u16,
+                (frame >> 48) as u16,
+            ]
+        })
+    }
+}
+
+impl Drop for Multiplay {
+    fn drop(&mut self) {
+        release();
+    }
+}
@@ -0,0 +1,107 @@
+use super::{claim, enable_irq, received, release, Mode, SerialError, RECEIVED, TO_SEND};
+use crate::util::without_irq;
+use core::ptr;
+
+#[derive(Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Clock {
+    External,
+    Internal256K,
+    Internal2M,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum NormalWidth {
+    Bits8,
+    Bits32,
+}
+
+// Starts the next transfer if the port is idle. The slave always arms itself, sending 0 when
+// nothing is queued, so it never misses a transfer from the master.
+fn start_transfer(master: bool, wide: bool) {
+    unsafe {
+        let control = ptr::read_volatile(0x4000128 as *const u16);
+        if control & 0x80 != 0 {
+            return;
+        }
+        let value = match TO_SEND.pop() {
+            Some(value) => value as u32,
+            None if master => return,
+            None => 0,
+        };
+        if wide {
+            ptr::write_volatile(0x4000120 as *mut u32, value); // Siodata32
+        } else {
+            ptr::write_volatile(0x400012A as *mut u8, value as u8); // Siodata8
+        }
+        ptr::write_volatile(0x4000128 as *mut u16, control | 0x80); // Start
+    }
+}
+
+#[link_section = ".fast_text"]
+pub(super) fn handle_irq(master: bool, wide: bool) {
+    let value = unsafe {
+        if wide {
+            ptr::read_volatile(0x4000120 as *const u32)
+        } else {
+            u32::from(ptr::read_volatile(0x400012A as *const u8))
+        }
+    };
+    received(u64::from(value));
+    start_transfer(master, wide);
+}
+
+// Normal mode talks to one other unit. The master drives the clock, so only it decides when
+// transfers happen and every value sent swaps with one from the slave.
+pub struct Normal {
+    master: bool,
+    wide: bool,
+}
+
+#[allow(dead_code)]
+impl Normal {
+    pub fn new(width: NormalWidth, clock: Clock) -> Result<Self, SerialError> {
+        let master = clock != Clock::External;
+        let wide = width == NormalWidth::Bits32;
+        claim(Mode::Normal { master, wide })?;
+        let control = match clock {
+            Clock::External => 0,
+            Clock::Internal256K => 0x1,
+            Clock::Internal2M => 0x3,
+        } | if wide { 0x1000 } else { 0 };
+        unsafe {
+            ptr::write_volatile(0x4000134 as *mut u16, 0); // Serial mode in rcnt
+            ptr::write_volatile(0x4000128 as *mut u16, control | 0x4000); // Siocnt with irq
+        }
+        enable_irq();
+        if !master {
+            without_irq(|| start_transfer(master, wide));
+        }
+        Ok(Normal { master, wide })
+    }
+
+    pub fn is_master(&self) -> bool {
+        self.master
+    }
+
+    // Returns false if the send queue is full
+    pub fn send(&mut self, value: u32) -> bool {
+        if !TO_SEND.push(u64::from(value)) {
+            return false;
+        }
+        let (master, wide) = (self.master, self.wide);
+        without_irq(|| start_transfer(master, wide));
+        true
+    }
+
+    pub fn receive(&mut self) -> Option<u32> {
+        RECEIVED.pop().map(|value| value as u32)
+    }
+}
+
+impl Drop for Normal {
+    fn drop(&mut self) {
+        release();
+    }
+}
@@ -0,0 +1,98 @@
+use super::{
+    claim, enable_irq, received, release, Baud, Mode, SerialError, ERRORS, RECEIVED, TO_SEND,
+};
+use crate::util::without_irq;
+use core::fmt;
+use core::ptr;
+
+// Moves queued bytes into the send fifo until it is full
+fn fill_fifo() {
+    unsafe {
+        while ptr::read_volatile(0x4000128 as *const u16) & 0x10 == 0 {
+            match TO_SEND.pop() {
+                Some(byte) => ptr::write_volatile(0x400012A as *mut u8, byte as u8), // Siodata8
+                None => break,
+            }
+        }
+    }
+}
+
+#[link_section = ".fast_text"]
+pub(super) fn handle_irq() {
+    unsafe {
+        if ptr::read_volatile(0x4000128 as *const u16) & 0x40 != 0 {
+            ERRORS.update(|errors| errors + 1);
+        }
+        while ptr::read_volatile(0x4000128 as *const u16) & 0x20 == 0 {
+            received(u64::from(ptr::read_volatile(0x400012A as *const u8)));
+        }
+    }
+    fill_fifo();
+}
+
+// 8 bit, no parity, with the hardware fifos. With flow control the port only sends while cts is
+// low.
+pub struct Uart {
+    _priv: (),
+}
+
+#[allow(dead_code)]
+impl Uart {
+    pub fn new(baud: Baud, flow_control: bool) -> Result<Self, SerialError> {
+        claim(Mode::Uart)?;
+        let control = baud.bits()
+            | if flow_control { 0x4 } else { 0 }
+            | 0x80 // 8 bit data
+            | 0xC00 // Send and receive enable
+            | 0x3000 // Uart mode
+            | 0x4000;
+        unsafe {
+            ptr::write_volatile(0x4000134 as *mut u16, 0); // Serial mode in rcnt
+            ptr::write_volatile(0x4000128 as *mut u16, control); // Setting up with the fifo off resets it
+            ptr::write_volatile(0x4000128 as *mut u16, control | 0x100); // Fifo enable
+        }
+        enable_irq();
+        Ok(Uart { _priv: () })
+    }
+
+    // Queues as much of `data` as fits and returns how much that was
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let count = data
+            .iter()
+            .take_while(|byte| TO_SEND.push(u64::from(**byte)))
+            .count();
+        without_irq(fill_fifo);
+        count
+    }
+
+    // Doesn't wait, returns 0 when nothing has arrived
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut count = 0;
+        for byte in buffer.iter_mut() {
+            match RECEIVED.pop() {
+                Some(received) => *byte = received as u8,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+// Waits for room in the send queue
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut data = s.as_bytes();
+        while !data.is_empty() {
+            let count = self.write(data);
+            data = &data[count..];
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Uart {
+    fn drop(&mut self) {
+        release();
+    }
+}
@@ -0,0 +1,219 @@
+use super::{SoundSource, MIX_RATE};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+#[link_section = ".fast_data"]
+static INDEX_TABLE: [i8; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+#[link_section = ".fast_data"]
+static STEP_TABLE: [u16; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImaAdpcmDecoder {
+    predictor: i32,
+    step_index: usize,
+}
+
+#[allow(dead_code)]
+impl ImaAdpcmDecoder {
+    pub fn new(predictor: i16, step_index: u8) -> Self {
+        ImaAdpcmDecoder {
+            predictor: i32::from(predictor),
+            step_index: usize::from(step_index).min(STEP_TABLE.len() - 1),
+        }
+    }
+
+    #[link_section = ".fast_text"]
+    fn decode_nibble(&mut self, nibble: u8) -> i16 {
+        let step = i32::from(STEP_TABLE[self.step_index]);
+        let mut diff = step >> 3;
+        if nibble & 4 != 0 {
+            diff += step;
+        }
+        if nibble & 2 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 1 != 0 {
+            diff += step >> 2;
+        }
+        if nibble & 8 != 0 {
+            diff = -diff;
+        }
+        self.predictor = (self.predictor + diff).clamp(i32::from(i16::MIN), i32::from(i16::MAX));
+        let index = self.step_index as i32 + i32::from(INDEX_TABLE[usize::from(nibble & 0xF)]);
+        self.step_index = index.clamp(0, STEP_TABLE.len() as i32 - 1) as usize;
+        self.predictor as i16
+    }
+
+    // Each byte holds two samples, low nibble first
+    #[link_section = ".fast_text"]
+    pub fn decode(&mut self, src: &[u8], dest: &mut [i8]) {
+        for (byte, pair) in src.iter().zip(dest.chunks_mut(2)) {
+            pair[0] = (self.decode_nibble(byte & 0xF) >> 8) as i8;
+            if let Some(high) = pair.get_mut(1) {
+                *high = (self.decode_nibble(byte >> 4) >> 8) as i8;
+            }
+        }
+    }
+}
+
+// Mono ima adpcm blocks as stored in wav files, each starting with a 4 byte header
+pub(super) fn samples_per_block(block_align: usize) -> usize {
+    (block_align - 4) * 2 + 1
+}
+
+pub(super) fn decode_block(block: &[u8], dest: &mut [i8]) -> usize {
+    if block.len() < 4 || dest.is_empty() {
+        return 0;
+    }
+    let predictor = i16::from_le_bytes(block[0..2].try_into().unwrap());
+    let mut decoder = ImaAdpcmDecoder::new(predictor, block[2]);
+    dest[0] = (predictor >> 8) as i8;
+    let len = samples_per_block(block.len()).min(dest.len());
+    decoder.decode(&block[4..], &mut dest[1..len]);
+    len
+}
+
+// Decodes a mono adpcm stream a block at a time and resamples it to the mixer rate
+pub struct AdpcmStream {
+    data: &'static [u8],
+    block_align: usize,
+    next_block: usize,
+    buffer: Vec<i8>,
+    buffer_len: usize,
+    position: u32, // 16.16 fixed point index into buffer
+    step: u32,
+    looping: bool,
+}
+
+#[allow(dead_code)]
+impl AdpcmStream {
+    pub(super) fn new(data: &'static [u8], block_align: usize, rate: u32) -> Self {
+        AdpcmStream {
+            data,
+            block_align,
+            next_block: 0,
+            buffer: vec![0; samples_per_block(block_align)],
+            buffer_len: 0,
+            position: 0,
+            step: ((u64::from(rate) << 16) / u64::from(MIX_RATE)) as u32,
+            looping: false,
+        }
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    pub fn restart(&mut self) {
+        self.next_block = 0;
+        self.buffer_len = 0;
+        self.position = 0;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.looping
+            && self.next_block * self.block_align >= self.data.len()
+            && (self.position >> 16) as usize >= self.buffer_len
+    }
+
+    fn load_block(&mut self) -> bool {
+        let begin = self.next_block * self.block_align;
+        if begin >= self.data.len() {
+            if !self.looping || self.data.is_empty() {
+                return false;
+            }
+            self.next_block = 0;
+            return self.load_block();
+        }
+        let end = (begin + self.block_align).min(self.data.len());
+        self.buffer_len = decode_block(&self.data[begin..end], &mut self.buffer);
+        self.next_block += 1;
+        true
+    }
+}
+
+impl SoundSource for AdpcmStream {
+    #[link_section = ".fast_text"]
+    fn mix(&mut self, left: &mut [i8], right: &mut [i8]) {
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            while (self.position >> 16) as usize >= self.buffer_len {
+                self.position -= (self.buffer_len as u32) << 16;
+                if !self.load_block() {
+                    self.buffer_len = 0;
+                    self.position = 0;
+                    break;
+                }
+            }
+            let value = if self.buffer_len == 0 {
+                0
+            } else {
+                self.buffer[(self.position >> 16) as usize]
+            };
+            *left = value;
+            *right = value;
+            self.position += self.step;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nibbles() {
+        let mut decoder = ImaAdpcmDecoder::new(0, 0);
+        let decoded: Vec<i16> = [7, 7, 7, 0xF, 0, 8]
+            .iter()
+            .map(|nibble| decoder.decode_nibble(*nibble))
+            .collect();
+        assert_eq!(decoded, [11, 41, 104, -32, -13, -30]);
+        assert_eq!(decoder.step_index, 30);
+
+        let mut decoder = ImaAdpcmDecoder::new(32000, 200);
+        assert_eq!(decoder.step_index, STEP_TABLE.len() - 1);
+        assert_eq!(decoder.decode_nibble(7), i16::MAX);
+        let mut decoder = ImaAdpcmDecoder::new(-32000, 88);
+        assert_eq!(decoder.decode_nibble(0xF), i16::MIN);
+    }
+
+    #[test]
+    fn low_nibble_first() {
+        let mut decoder = ImaAdpcmDecoder::new(0x1000, 20);
+        let mut dest = [0; 8];
+        decoder.decode(&[0x21, 0x43, 0x65, 0x87], &mut dest);
+        assert_eq!(dest, [16, 16, 16, 16, 16, 17, 17, 17]);
+
+        // An odd length only takes the low nibble of the last byte
+        let mut decoder = ImaAdpcmDecoder::new(0x1000, 20);
+        let mut dest = [0; 3];
+        decoder.decode(&[0x21, 0x43, 0x65], &mut dest);
+        assert_eq!(dest, [16, 16, 16]);
+        assert_eq!(decoder.predictor, 4176);
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(samples_per_block(36), 65);
+        assert_eq!(samples_per_block(256), 505);
+
+        let block = [0x00, 0x10, 20, 0, 0x21, 0x43, 0x65, 0x87];
+        let mut dest = [0; 16];
+        assert_eq!(decode_block(&block, &mut dest), 9);
+        assert_eq!(&dest[..9], &[16, 16, 16, 16, 16, 16, 17, 17, 17]);
+
+        let mut short = [0; 4];
+        assert_eq!(decode_block(&block, &mut short), 4);
+        assert_eq!(short, [16, 16, 16, 16]);
+        assert_eq!(decode_block(&block[..3], &mut dest), 0);
+        assert_eq!(decode_block(&block, &mut []), 0);
+    }
+}
@@ -0,0 +1,154 @@
+use super::{Sample, SoundSource, BUFFER_LEN, MIX_RATE};
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const MAX_VOLUME: u8 = 64;
+pub const MAX_PAN: i8 = 64;
+
+#[derive(Clone, Copy)]
+struct Voice<'a> {
+    sample: Option<Sample<'a>>,
+    position: usize,
+    fraction: u32, // 16 bit fraction of position
+    step: u32,     // 16.16 fixed point samples per output sample
+    volume: u8,    // 0..=64
+    pan: i8,       // -64 (left) ..= 64 (right)
+}
+
+impl<'a> Voice<'a> {
+    const IDLE: Voice<'static> = Voice {
+        sample: None,
+        position: 0,
+        fraction: 0,
+        step: 0,
+        volume: MAX_VOLUME,
+        pan: 0,
+    };
+}
+
+pub struct Mixer<'a, const VOICES: usize> {
+    voices: [Voice<'a>; VOICES],
+    master_volume: u8,
+    left: Vec<i32>,
+    right: Vec<i32>,
+}
+
+fn step_for_rate(rate: u32) -> u32 {
+    ((u64::from(rate) << 16) / u64::from(MIX_RATE)) as u32
+}
+
+#[allow(dead_code)]
+impl<'a, const VOICES: usize> Mixer<'a, VOICES> {
+    pub fn new() -> Self {
+        Mixer {
+            voices: [Voice::IDLE; VOICES],
+            master_volume: MAX_VOLUME,
+            left: vec![0; BUFFER_LEN],
+            right: vec![0; BUFFER_LEN],
+        }
+    }
+
+    pub fn play(&mut self, voice: usize, sample: Sample<'a>) {
+        let voice = &mut self.voices[voice];
+        voice.sample = Some(sample);
+        voice.position = 0;
+        voice.fraction = 0;
+        voice.step = step_for_rate(sample.rate);
+    }
+
+    pub fn stop(&mut self, voice: usize) {
+        self.voices[voice].sample = None;
+    }
+
+    pub fn is_playing(&self, voice: usize) -> bool {
+        self.voices[voice].sample.is_some()
+    }
+
+    pub fn set_volume(&mut self, voice: usize, volume: u8) {
+        self.voices[voice].volume = volume.min(MAX_VOLUME);
+    }
+
+    pub fn set_pan(&mut self, voice: usize, pan: i8) {
+        self.voices[voice].pan = pan.clamp(-MAX_PAN, MAX_PAN);
+    }
+
+    // Playback rate of the sample in hz
+    pub fn set_frequency(&mut self, voice: usize, rate: u32) {
+        self.voices[voice].step = step_for_rate(rate);
+    }
+
+    pub fn set_position(&mut self, voice: usize, position: usize) {
+        let voice = &mut self.voices[voice];
+        voice.position = position;
+        voice.fraction = 0;
+    }
+
+    pub fn set_master_volume(&mut self, volume: u8) {
+        self.master_volume = volume.min(MAX_VOLUME);
+    }
+}
+
+impl<'a, const VOICES: usize> Default for Mixer<'a, VOICES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Adds one voice into the accumulators, returns false once a non looping sample ends
+#[link_section = ".fast_text"]
+fn mix_voice(voice: &mut Voice, master_volume: u8, left: &mut [i32], right: &mut [i32]) -> bool {
+    let sample = match voice.sample {
+        Some(sample) => sample,
+        None => return false,
+    };
+    let data = sample.data;
+    let volume = i32::from(voice.volume) * i32::from(master_volume);
+    let left_volume = volume * i32::from(MAX_PAN - voice.pan);
+    let right_volume = volume * i32::from(MAX_PAN + voice.pan);
+
+    for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+        if voice.position >= data.len() {
+            match sample.loop_start {
+                Some(loop_start) => {
+                    let loop_len = data.len() - loop_start;
+                    voice.position = loop_start + (voice.position - data.len()) % loop_len;
+                }
+                None => return false,
+            }
+        }
+
+        let value = i32::from(data[voice.position]);
+        *left += value * left_volume;
+        *right += value * right_volume;
+
+        voice.fraction += voice.step;
+        voice.position += (voice.fraction >> 16) as usize;
+        voice.fraction &= 0xFFFF;
+    }
+
+    true
+}
+
+impl<'a, const VOICES: usize> SoundSource for Mixer<'a, VOICES> {
+    #[link_section = ".fast_text"]
+    fn mix(&mut self, left: &mut [i8], right: &mut [i8]) {
+        let len = left.len();
+        let (left_acc, right_acc) = (&mut self.left[..len], &mut self.right[..len]);
+        left_acc.fill(0);
+        right_acc.fill(0);
+
+        for voice in self.voices.iter_mut() {
+            if !mix_voice(voice, self.master_volume, left_acc, right_acc) {
+                voice.sample = None;
+            }
+        }
+
+        // Volume * master volume * pan is 6 + 6 + 7 bits
+        for (dest, acc) in left.iter_mut().zip(left_acc.iter()) {
+            *dest = (acc >> 19).clamp(-128, 127) as i8;
+        }
+        for (dest, acc) in right.iter_mut().zip(right_acc.iter()) {
+            *dest = (acc >> 19).clamp(-128, 127) as i8;
+        }
+    }
+}
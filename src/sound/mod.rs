@@ -0,0 +1,196 @@
+use crate::file::RomFile;
+use crate::volatile::{VolatileBool, VolatileCell};
+use core::ptr;
+use core::slice;
+
+mod adpcm;
+mod mixer;
+pub mod psg;
+mod sfx;
+pub mod tracker;
+mod wav;
+#[allow(unused_imports)]
+pub use adpcm::{AdpcmStream, ImaAdpcmDecoder};
+#[allow(unused_imports)]
+pub use mixer::Mixer;
+#[allow(unused_imports)]
+pub use psg::Psg;
+#[allow(unused_imports)]
+pub use sfx::{SfxError, SfxPlayer};
+#[allow(unused_imports)]
+pub use wav::{WavEncoding, WavError, WavFile};
+
+// 304 samples at 18157hz is exactly one frame
+pub const MIX_RATE: u32 = 18157;
+pub const BUFFER_LEN: usize = 304;
+const TIMER_RELOAD: u16 = (0x10000 - 924) as u16; // 16.78mhz / 924 = 18157hz
+
+// The fifo dma reads up to 16 bytes past the end before the buffer swap irq lands
+const BUFFER_PADDING: usize = 16;
+
+#[repr(C, align(4))]
+pub struct SoundBuffer {
+    left: [i8; BUFFER_LEN + BUFFER_PADDING],
+    right: [i8; BUFFER_LEN + BUFFER_PADDING], // The irq expects right at left + 320
+}
+
+#[link_section = ".fast_bss"]
+static mut SOUND_BUFFERS: [SoundBuffer; 2] = [
+    SoundBuffer {
+        left: [0; BUFFER_LEN + BUFFER_PADDING],
+        right: [0; BUFFER_LEN + BUFFER_PADDING],
+    },
+    SoundBuffer {
+        left: [0; BUFFER_LEN + BUFFER_PADDING],
+        right: [0; BUFFER_LEN + BUFFER_PADDING],
+    },
+];
+
+// Incremented by the timer 1 irq every time the buffers are swapped
+#[no_mangle]
+static SOUND_FRAME: VolatileCell<u32> = VolatileCell::new(0);
+
+// The buffer the irq starts playing on the next swap
+#[no_mangle]
+static SOUND_NEXT_BUFFER: VolatileCell<*const SoundBuffer> = VolatileCell::new(ptr::null());
+
+static SOUND_USED: VolatileBool = VolatileBool::new(false);
+
+#[allow(dead_code)]
+pub trait SoundSource {
+    fn mix(&mut self, left: &mut [i8], right: &mut [i8]);
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sample<'a> {
+    data: &'a [i8],
+    loop_start: Option<usize>,
+    rate: u32,
+}
+
+#[allow(dead_code)]
+impl<'a> Sample<'a> {
+    pub fn new(data: &'a [i8], rate: u32) -> Self {
+        Sample {
+            data,
+            loop_start: None,
+            rate,
+        }
+    }
+
+    pub fn with_loop(mut self, loop_start: usize) -> Self {
+        assert!(
+            loop_start < self.data.len(),
+            "Loop starts past the sample end"
+        );
+        self.loop_start = Some(loop_start);
+        self
+    }
+
+    pub fn data(&self) -> &'a [i8] {
+        self.data
+    }
+
+    pub fn loop_start(&self) -> Option<usize> {
+        self.loop_start
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+}
+
+#[allow(dead_code)]
+impl Sample<'static> {
+    // The file is raw 8 bit signed pcm
+    pub fn from_file(file: &RomFile, rate: u32) -> Self {
+        let bytes = file.as_bytes();
+        let data = unsafe { slice::from_raw_parts(bytes.as_ptr() as *const i8, bytes.len()) };
+        Self::new(data, rate)
+    }
+}
+
+pub struct DirectSound {
+    back: usize,
+    last_frame: u32,
+}
+
+#[allow(dead_code)]
+impl DirectSound {
+    pub fn new() -> Self {
+        assert!(!SOUND_USED.swap(true), "Direct sound is already running");
+        unsafe {
+            let buffers = &mut *ptr::addr_of_mut!(SOUND_BUFFERS);
+            for buffer in buffers.iter_mut() {
+                buffer.left.fill(0);
+                buffer.right.fill(0);
+            }
+            SOUND_NEXT_BUFFER.write(&buffers[0]);
+
+            ptr::write_volatile(0x4000084 as *mut u16, 0x80); // Master sound enable
+            let psg_volume = ptr::read_volatile(0x4000082 as *const u16) & 3;
+            ptr::write_volatile(0x4000082 as *mut u16, psg_volume | 0x9A0C); // A left, B right, full volume, both on timer 0, reset fifos
+
+            ptr::write_volatile(0x40000BC as *mut *const i8, buffers[0].left.as_ptr()); // Dma 1 source
+            ptr::write_volatile(0x40000C0 as *mut u32, 0x40000A0); // Dma 1 dest fifo a
+            ptr::write_volatile(0x40000C6 as *mut u16, 0xB640); // Dma 1 fifo mode
+            ptr::write_volatile(0x40000C8 as *mut *const i8, buffers[0].right.as_ptr()); // Dma 2 source
+            ptr::write_volatile(0x40000CC as *mut u32, 0x40000A4); // Dma 2 dest fifo b
+            ptr::write_volatile(0x40000D2 as *mut u16, 0xB640); // Dma 2 fifo mode
+
+            ptr::write_volatile(0x4000104 as *mut u16, (0x10000 - BUFFER_LEN) as u16); // Timer 1 reload
+            ptr::write_volatile(0x4000106 as *mut u16, 0xC4); // Timer 1 cascade with irqs
+            let enabled = ptr::read_volatile(0x4000200 as *const u16);
+            ptr::write_volatile(0x4000200 as *mut u16, enabled | 0x10); // Turn on timer 1 irq
+
+            ptr::write_volatile(0x4000100 as *mut u16, TIMER_RELOAD); // Timer 0 reload
+            ptr::write_volatile(0x4000102 as *mut u16, 0x80); // Start timer 0
+        }
+
+        DirectSound {
+            back: 1,
+            last_frame: SOUND_FRAME.read().wrapping_sub(1),
+        }
+    }
+
+    // Mixes the next buffer if the irq has swapped since the last call, should be called at least once per frame
+    pub fn update<S: SoundSource + ?Sized>(&mut self, source: &mut S) -> bool {
+        let frame = SOUND_FRAME.read();
+        if frame == self.last_frame {
+            return false;
+        }
+
+        let buffer = unsafe { &mut (*ptr::addr_of_mut!(SOUND_BUFFERS))[self.back] };
+        source.mix(
+            &mut buffer.left[..BUFFER_LEN],
+            &mut buffer.right[..BUFFER_LEN],
+        );
+        SOUND_NEXT_BUFFER.write(buffer);
+
+        self.back ^= 1;
+        self.last_frame = frame;
+        true
+    }
+}
+
+impl Default for DirectSound {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DirectSound {
+    fn drop(&mut self) {
+        unsafe {
+            let enabled = ptr::read_volatile(0x4000200 as *const u16);
+            ptr::write_volatile(0x4000200 as *mut u16, enabled & !0x10);
+            ptr::write_volatile(0x4000102 as *mut u16, 0); // Stop timer 0
+            ptr::write_volatile(0x4000106 as *mut u16, 0); // Stop timer 1
+            ptr::write_volatile(0x40000C6 as *mut u16, 0); // Stop dma 1
+            ptr::write_volatile(0x40000D2 as *mut u16, 0); // Stop dma 2
+            let control = ptr::read_volatile(0x4000082 as *const u16);
+            ptr::write_volatile(0x4000082 as *mut u16, control & !0x3300); // Disconnect a and b
+        }
+        SOUND_USED.write(false);
+    }
+}
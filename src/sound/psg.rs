@@ -0,0 +1,288 @@
+use core::marker::PhantomData;
+use core::ptr;
+
+fn write_reg(addr: usize, value: u16) {
+    unsafe { ptr::write_volatile(addr as *mut u16, value) }
+}
+
+fn read_reg(addr: usize) -> u16 {
+    unsafe { ptr::read_volatile(addr as *const u16) }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Decrease,
+    Increase,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    pub volume: u8, // 0..=15
+    pub direction: Direction,
+    pub step_time: u8, // 0..=7 in 1/64 seconds, 0 holds the volume
+}
+
+impl Envelope {
+    #[allow(dead_code)]
+    pub const fn constant(volume: u8) -> Self {
+        Envelope {
+            volume,
+            direction: Direction::Decrease,
+            step_time: 0,
+        }
+    }
+
+    fn bits(&self) -> u16 {
+        let direction = (self.direction == Direction::Increase) as u16;
+        (u16::from(self.volume & 0xF) << 12)
+            | (direction << 11)
+            | (u16::from(self.step_time & 7) << 8)
+    }
+
+    pub(super) fn from_bits(bits: u16) -> Self {
+        Envelope {
+            volume: (bits >> 12) as u8,
+            direction: if bits & 0x800 != 0 {
+                Direction::Increase
+            } else {
+                Direction::Decrease
+            },
+            step_time: ((bits >> 8) & 7) as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Duty {
+    Eighth,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl Duty {
+    fn bits(self) -> u16 {
+        (self as u16) << 6
+    }
+
+    pub(super) fn from_bits(bits: u16) -> Self {
+        match (bits >> 6) & 3 {
+            0 => Duty::Eighth,
+            1 => Duty::Quarter,
+            2 => Duty::Half,
+            _ => Duty::ThreeQuarters,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sweep {
+    pub shift: u8, // 0..=7
+    pub direction: Direction,
+    pub time: u8, // 0..=7 in 1/128 seconds, 0 turns off the sweep
+}
+
+impl Sweep {
+    #[allow(dead_code)]
+    pub const OFF: Sweep = Sweep {
+        shift: 0,
+        direction: Direction::Increase,
+        time: 0,
+    };
+
+    fn bits(&self) -> u16 {
+        let direction = (self.direction == Direction::Decrease) as u16;
+        u16::from(self.shift & 7) | (direction << 3) | (u16::from(self.time & 7) << 4)
+    }
+
+    pub(super) fn from_bits(bits: u16) -> Self {
+        Sweep {
+            shift: (bits & 7) as u8,
+            direction: if bits & 8 != 0 {
+                Direction::Decrease
+            } else {
+                Direction::Increase
+            },
+            time: ((bits >> 4) & 7) as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaveVolume {
+    Mute,
+    Full,
+    Half,
+    Quarter,
+    ThreeQuarters,
+}
+
+impl WaveVolume {
+    fn bits(self) -> u16 {
+        match self {
+            WaveVolume::Mute => 0,
+            WaveVolume::Full => 0x2000,
+            WaveVolume::Half => 0x4000,
+            WaveVolume::Quarter => 0x6000,
+            WaveVolume::ThreeQuarters => 0x8000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Noise {
+    pub ratio: u8,   // 0..=7
+    pub short: bool, // 7 bit counter instead of 15 bit
+    pub shift: u8,   // 0..=13
+}
+
+impl Noise {
+    fn bits(&self) -> u16 {
+        u16::from(self.ratio & 7)
+            | (u16::from(self.short) << 3)
+            | (u16::from(self.shift & 0xF) << 4)
+    }
+
+    pub(super) fn from_bits(bits: u16) -> Self {
+        Noise {
+            ratio: (bits & 7) as u8,
+            short: bits & 8 != 0,
+            shift: ((bits >> 4) & 0xF) as u8,
+        }
+    }
+}
+
+// Rate value for the square channels
+#[allow(dead_code)]
+pub fn square_rate(hz: u32) -> u16 {
+    let hz = hz.clamp(64, 131072);
+    (2048 - 131072 / hz) as u16
+}
+
+// Rate value for the wave channel playing a 32 sample wave
+#[allow(dead_code)]
+pub fn wave_rate(hz: u32) -> u16 {
+    let hz = hz.clamp(32, 65536);
+    (2048 - 65536 / hz) as u16
+}
+
+fn length_bits(length: Option<u16>, max: u16) -> (u16, u16) {
+    match length {
+        Some(length) => (max - length.clamp(1, max), 0x4000),
+        None => (0, 0),
+    }
+}
+
+pub struct Psg {
+    _priv: PhantomData<*mut ()>,
+}
+
+#[allow(dead_code)]
+impl Psg {
+    pub fn new() -> Self {
+        write_reg(0x4000084, read_reg(0x4000084) | 0x80); // Master sound enable
+        write_reg(0x4000080, 0xFF77); // All channels on both sides at full volume
+        write_reg(0x4000082, (read_reg(0x4000082) & !3) | 2); // Psg at 100%
+        Psg { _priv: PhantomData }
+    }
+
+    // Volumes are 0..=7
+    pub fn set_master_volume(&mut self, left: u8, right: u8) {
+        let control = read_reg(0x4000080) & 0xFF00;
+        write_reg(
+            0x4000080,
+            control | (u16::from(left & 7) << 4) | u16::from(right & 7),
+        );
+    }
+
+    pub fn route(&mut self, channel: Channel, left: bool, right: bool) {
+        let bit = 1 << (channel as u16);
+        let mut control = read_reg(0x4000080) & !((bit << 8) | (bit << 12));
+        if right {
+            control |= bit << 8;
+        }
+        if left {
+            control |= bit << 12;
+        }
+        write_reg(0x4000080, control);
+    }
+
+    pub fn is_playing(&self, channel: Channel) -> bool {
+        read_reg(0x4000084) & (1 << (channel as u16)) != 0
+    }
+
+    // Length is in 1/256 seconds, None plays until stopped
+    pub fn play_square1(
+        &mut self,
+        sweep: Sweep,
+        duty: Duty,
+        envelope: Envelope,
+        rate: u16,
+        length: Option<u8>,
+    ) {
+        let (length, length_flag) = length_bits(length.map(u16::from), 64);
+        write_reg(0x4000060, sweep.bits());
+        write_reg(0x4000062, envelope.bits() | duty.bits() | length);
+        write_reg(0x4000064, (rate & 0x7FF) | length_flag | 0x8000);
+    }
+
+    pub fn play_square2(&mut self, duty: Duty, envelope: Envelope, rate: u16, length: Option<u8>) {
+        let (length, length_flag) = length_bits(length.map(u16::from), 64);
+        write_reg(0x4000068, envelope.bits() | duty.bits() | length);
+        write_reg(0x400006C, (rate & 0x7FF) | length_flag | 0x8000);
+    }
+
+    // Stops the wave channel, wave ram writes go to the bank that isn't selected for playback
+    pub fn load_wave(&mut self, wave: &[u8; 16]) {
+        write_reg(0x4000070, 0x40); // Select bank 1 so bank 0 is written
+        for (i, pair) in wave.chunks_exact(2).enumerate() {
+            write_reg(0x4000090 + i * 2, u16::from_le_bytes([pair[0], pair[1]]));
+        }
+    }
+
+    // The wave channel counts up to 256 instead of 64
+    pub fn play_wave(&mut self, volume: WaveVolume, rate: u16, length: Option<u16>) {
+        let (length, length_flag) = length_bits(length, 256);
+        write_reg(0x4000070, 0x80); // Play bank 0
+        write_reg(0x4000072, volume.bits() | length);
+        write_reg(0x4000074, (rate & 0x7FF) | length_flag | 0x8000);
+    }
+
+    pub fn play_noise(&mut self, noise: Noise, envelope: Envelope, length: Option<u8>) {
+        let (length, length_flag) = length_bits(length.map(u16::from), 64);
+        write_reg(0x4000078, envelope.bits() | length);
+        write_reg(0x400007C, noise.bits() | length_flag | 0x8000);
+    }
+
+    pub fn stop(&mut self, channel: Channel) {
+        match channel {
+            Channel::Square1 => {
+                write_reg(0x4000062, 0);
+                write_reg(0x4000064, 0x8000);
+            }
+            Channel::Square2 => {
+                write_reg(0x4000068, 0);
+                write_reg(0x400006C, 0x8000);
+            }
+            Channel::Wave => write_reg(0x4000070, 0),
+            Channel::Noise => {
+                write_reg(0x4000078, 0);
+                write_reg(0x400007C, 0x8000);
+            }
+        }
+    }
+}
+
+impl Default for Psg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
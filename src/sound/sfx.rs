@@ -0,0 +1,134 @@
+use super::psg::{Channel, Duty, Envelope, Noise, Psg, Sweep, WaveVolume};
+use crate::file::RomFile;
+use core::convert::TryInto;
+
+const STEP_SIZE: usize = 8;
+const END_CHANNEL: u8 = 0xFF;
+
+#[derive(Debug)]
+pub enum SfxError {
+    Truncated,
+    #[allow(dead_code)]
+    BadChannel(u8),
+}
+
+struct Step {
+    channel: Channel,
+    wait: u8,
+    rate: u16,
+    control: u16,
+    sweep: u8,
+    use_length: bool,
+}
+
+impl Step {
+    fn parse(raw: &[u8]) -> Result<Option<Self>, SfxError> {
+        let channel = match raw[0] {
+            0 => Channel::Square1,
+            1 => Channel::Square2,
+            2 => Channel::Wave,
+            3 => Channel::Noise,
+            END_CHANNEL => return Ok(None),
+            other => return Err(SfxError::BadChannel(other)),
+        };
+        Ok(Some(Step {
+            channel,
+            wait: raw[1],
+            rate: u16::from_le_bytes(raw[2..4].try_into().unwrap()),
+            control: u16::from_le_bytes(raw[4..6].try_into().unwrap()),
+            sweep: raw[6],
+            use_length: raw[7] & 1 != 0,
+        }))
+    }
+
+    fn play(&self, psg: &mut Psg) {
+        let envelope = Envelope::from_bits(self.control);
+        let length = if self.use_length {
+            Some(64 - (self.control & 0x3F) as u8)
+        } else {
+            None
+        };
+        match self.channel {
+            Channel::Square1 => psg.play_square1(
+                Sweep::from_bits(u16::from(self.sweep)),
+                Duty::from_bits(self.control),
+                envelope,
+                self.rate,
+                length,
+            ),
+            Channel::Square2 => {
+                psg.play_square2(Duty::from_bits(self.control), envelope, self.rate, length)
+            }
+            Channel::Wave => {
+                let volume = match self.control >> 13 {
+                    0 => WaveVolume::Mute,
+                    1 => WaveVolume::Full,
+                    2 => WaveVolume::Half,
+                    3 => WaveVolume::Quarter,
+                    _ => WaveVolume::ThreeQuarters,
+                };
+                let length = if self.use_length {
+                    Some(256 - (self.control & 0xFF))
+                } else {
+                    None
+                };
+                psg.play_wave(volume, self.rate, length)
+            }
+            Channel::Noise => psg.play_noise(Noise::from_bits(self.rate), envelope, length),
+        }
+    }
+}
+
+// Plays a sound effect in the format described in doc/sfx.txt
+pub struct SfxPlayer {
+    data: &'static [u8],
+    index: usize,
+    wait: u8,
+}
+
+#[allow(dead_code)]
+impl SfxPlayer {
+    pub fn new(data: &'static [u8]) -> Result<Self, SfxError> {
+        if !data.len().is_multiple_of(STEP_SIZE) {
+            return Err(SfxError::Truncated);
+        }
+        for raw in data.chunks_exact(STEP_SIZE) {
+            if Step::parse(raw)?.is_none() {
+                break;
+            }
+        }
+        Ok(SfxPlayer {
+            data,
+            index: 0,
+            wait: 0,
+        })
+    }
+
+    pub fn from_file(file: &RomFile) -> Result<Self, SfxError> {
+        Self::new(file.as_bytes())
+    }
+
+    pub fn restart(&mut self) {
+        self.index = 0;
+        self.wait = 0;
+    }
+
+    // Should be called once per frame, returns false once the effect is over
+    pub fn tick(&mut self, psg: &mut Psg) -> bool {
+        while self.wait == 0 {
+            let step = match self.data.get(self.index..self.index + STEP_SIZE) {
+                Some(raw) => Step::parse(raw).unwrap(),
+                None => None,
+            };
+            let step = match step {
+                Some(step) => step,
+                None => return false,
+            };
+            step.play(psg);
+            self.wait = step.wait;
+            self.index += STEP_SIZE;
+        }
+        self.wait -= 1;
+        true
+    }
+}
@@ -0,0 +1,233 @@
+use crate::file::RomFile;
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+mod player;
+mod protracker;
+mod xm;
+#[allow(unused_imports)]
+pub use player::Player;
+
+pub const NOTE_OFF: u8 = 97;
+
+#[derive(Debug)]
+pub enum TrackerError {
+    BadSignature,
+    Truncated,
+    #[allow(dead_code)]
+    UnsupportedVersion(u16),
+    #[allow(dead_code)]
+    BadChannelCount(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    ProTracker,
+    FastTracker,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FrequencyMode {
+    Amiga,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Cell {
+    note: u8, // 1..=96 with C-0 as 1, NOTE_OFF or 0 for none
+    instrument: u8,
+    volume: u8, // Raw xm volume column
+    effect: u8,
+    param: u8,
+}
+
+struct Pattern {
+    rows: u16,
+    data: &'static [u8],
+}
+
+struct TrackerSample {
+    data: Cow<'static, [i8]>,
+    loop_start: Option<usize>,
+    volume: u8,
+    finetune: i8, // In 1/128 semitones
+    relative_note: i8,
+    pan: i8,
+}
+
+struct Instrument {
+    sample_map: [u8; 96],
+    first_sample: usize,
+    sample_count: usize,
+}
+
+pub struct Song {
+    format: Format,
+    frequency_mode: FrequencyMode,
+    channels: usize,
+    orders: Vec<u8>,
+    restart: usize,
+    patterns: Vec<Pattern>,
+    instruments: Vec<Instrument>,
+    samples: Vec<TrackerSample>,
+    initial_speed: u8,
+    initial_tempo: u8,
+    default_pan: Vec<i8>,
+}
+
+fn read_bytes(data: &[u8], offset: usize, len: usize) -> Result<&[u8], TrackerError> {
+    data.get(offset..offset + len)
+        .ok_or(TrackerError::Truncated)
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8, TrackerError> {
+    data.get(offset).copied().ok_or(TrackerError::Truncated)
+}
+
+fn read_u16_be(data: &[u8], offset: usize) -> Result<u16, TrackerError> {
+    Ok(u16::from_be_bytes(
+        read_bytes(data, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> Result<u16, TrackerError> {
+    Ok(u16::from_le_bytes(
+        read_bytes(data, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32, TrackerError> {
+    Ok(u32::from_le_bytes(
+        read_bytes(data, offset, 4)?.try_into().unwrap(),
+    ))
+}
+
+// Periods of the octave starting at C-0 (note 24) in protracker units, so C-2 (note 48) is 428
+static AMIGA_PERIODS: [i32; 12] = [
+    1712, 1616, 1525, 1440, 1357, 1281, 1209, 1141, 1077, 1017, 961, 907,
+];
+
+// 2^(n/12) in 16.16 fixed point
+static SEMITONE_RATIOS: [u32; 13] = [
+    65536, 69433, 73562, 77936, 82570, 87480, 92682, 98193, 104032, 110218, 116772, 123715, 131072,
+];
+
+#[allow(dead_code)]
+impl Song {
+    pub fn from_mod(file: &RomFile) -> Result<Self, TrackerError> {
+        protracker::parse(file.as_bytes())
+    }
+
+    pub fn from_xm(file: &RomFile) -> Result<Self, TrackerError> {
+        xm::parse(file.as_bytes())
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn amiga_period(note: i32) -> i32 {
+        let note = note.clamp(0, 119);
+        (AMIGA_PERIODS[(note % 12) as usize] << 2) >> (note / 12)
+    }
+
+    // Note is 0 based, finetune is in 1/128 semitones
+    fn note_period(&self, note: i32, finetune: i32) -> i32 {
+        match self.frequency_mode {
+            FrequencyMode::Linear => 7680 - note * 64 - finetune / 2,
+            FrequencyMode::Amiga => {
+                let period = Self::amiga_period(note);
+                if finetune >= 0 {
+                    period - (period - Self::amiga_period(note + 1)) * finetune / 128
+                } else {
+                    period + (Self::amiga_period(note - 1) - period) * -finetune / 128
+                }
+            }
+        }
+    }
+
+    // Linear periods move 4 times slower than amiga ones for the same effect param
+    fn period_scale(&self) -> i32 {
+        match self.frequency_mode {
+            FrequencyMode::Linear => 4,
+            FrequencyMode::Amiga => 1,
+        }
+    }
+
+    fn period_range(&self) -> (i32, i32) {
+        match self.frequency_mode {
+            FrequencyMode::Linear => (64, 7680),
+            FrequencyMode::Amiga => (28, 6848),
+        }
+    }
+
+    fn period_frequency(&self, period: i32) -> u32 {
+        let (min, max) = self.period_range();
+        let period = period.clamp(min, max);
+        match self.frequency_mode {
+            FrequencyMode::Amiga => 3546895 / period as u32,
+            FrequencyMode::Linear => {
+                // 8363 * 2^((4608 - period) / 768)
+                let exponent = 4608 - period;
+                let octave = exponent.div_euclid(768);
+                let within = exponent.rem_euclid(768);
+                let (semitone, fine) = ((within / 64) as usize, (within % 64) as u32);
+                let low = SEMITONE_RATIOS[semitone];
+                let high = SEMITONE_RATIOS[semitone + 1];
+                let ratio = u64::from(low + (high - low) * fine / 64);
+                let freq = 8363 * ratio;
+                let freq = if octave >= 0 {
+                    freq << octave
+                } else {
+                    freq >> -octave
+                };
+                (freq >> 16) as u32
+            }
+        }
+    }
+
+    fn find_sample(&self, instrument: u8, note: u8) -> Option<&TrackerSample> {
+        let instrument = self
+            .instruments
+            .get(usize::from(instrument).checked_sub(1)?)?;
+        let index = usize::from(
+            *instrument
+                .sample_map
+                .get(usize::from(note).checked_sub(1)?)?,
+        );
+        if index >= instrument.sample_count {
+            return None;
+        }
+        self.samples.get(instrument.first_sample + index)
+    }
+
+    // Decodes the row at `offset` and returns the offset of the next one
+    fn decode_row(&self, pattern: usize, offset: usize, cells: &mut [Cell]) -> usize {
+        let pattern = match self.patterns.get(pattern) {
+            Some(pattern) => pattern,
+            None => {
+                cells.fill(Cell::default());
+                return offset;
+            }
+        };
+        match self.format {
+            Format::ProTracker => protracker::decode_row(self, pattern.data, offset, cells),
+            Format::FastTracker => xm::decode_row(pattern.data, offset, cells),
+        }
+    }
+
+    fn skip_rows(&self, pattern: usize, rows: u16, scratch: &mut [Cell]) -> usize {
+        let mut offset = 0;
+        for _ in 0..rows {
+            offset = self.decode_row(pattern, offset, scratch);
+        }
+        offset
+    }
+
+    fn pattern_rows(&self, pattern: usize) -> u16 {
+        self.patterns
+            .get(pattern)
+            .map_or(64, |pattern| pattern.rows)
+    }
+}
@@ -0,0 +1,380 @@
+use super::{Cell, Format, Song, NOTE_OFF};
+use crate::sound::mixer::{MAX_PAN, MAX_VOLUME};
+use crate::sound::{Mixer, Sample, SoundSource, MIX_RATE};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::mem;
+
+static VIBRATO_SINE: [i32; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253, 255, 253, 250, 244,
+    235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+#[derive(Clone, Copy, Default)]
+struct ChannelState {
+    instrument: u8,
+    note: i32, // 0 based including the sample's relative note
+    finetune: i32,
+    period: i32,
+    target_period: i32,
+    volume: u8,
+    pan: i8,
+    effect: u8,
+    param: u8,
+    volume_column: u8,
+    porta_speed: u8,
+    tone_porta_speed: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_position: u8,
+    volume_slide: u8,
+    arpeggio: Option<(i32, i32)>,
+}
+
+impl ChannelState {
+    fn slide_volume(&mut self, param: u8) {
+        let volume = if param >> 4 != 0 {
+            self.volume.saturating_add(param >> 4)
+        } else {
+            self.volume.saturating_sub(param & 0xF)
+        };
+        self.volume = volume.min(MAX_VOLUME);
+    }
+
+    fn tone_porta(&mut self, scale: i32) {
+        let speed = i32::from(self.tone_porta_speed) * scale;
+        if self.period < self.target_period {
+            self.period = min(self.period + speed, self.target_period);
+        } else {
+            self.period = (self.period - speed).max(self.target_period);
+        }
+    }
+
+    fn vibrato_offset(&self, scale: i32) -> i32 {
+        let sine = VIBRATO_SINE[usize::from(self.vibrato_position & 31)];
+        let offset = ((sine * i32::from(self.vibrato_depth)) >> 7) * scale;
+        if self.vibrato_position & 32 != 0 {
+            -offset
+        } else {
+            offset
+        }
+    }
+}
+
+// Plays a song through its own mixer. The song advances as buffers are mixed, so its timing
+// follows the timer irq that paces `DirectSound::update`.
+pub struct Player<'s, const CHANNELS: usize> {
+    song: &'s Song,
+    mixer: Mixer<'s, CHANNELS>,
+    channels: [ChannelState; CHANNELS],
+    cells: Vec<Cell>,
+    order: usize,
+    row: u16,
+    row_offset: usize,
+    tick: u8,
+    speed: u8,
+    tempo: u8,
+    tick_samples_left: u32, // 24.8 fixed point
+    jump: Option<(usize, u16)>,
+}
+
+#[allow(dead_code)]
+impl<'s, const CHANNELS: usize> Player<'s, CHANNELS> {
+    pub fn new(song: &'s Song) -> Self {
+        assert!(
+            song.channels <= CHANNELS,
+            "Song has too many channels for the player"
+        );
+        let mut player = Player {
+            song,
+            mixer: Mixer::new(),
+            channels: [ChannelState::default(); CHANNELS],
+            cells: vec![Cell::default(); song.channels],
+            order: 0,
+            row: 0,
+            row_offset: 0,
+            tick: 0,
+            speed: song.initial_speed,
+            tempo: song.initial_tempo,
+            tick_samples_left: 0,
+            jump: None,
+        };
+        for (channel, pan) in player.channels.iter_mut().zip(song.default_pan.iter()) {
+            channel.pan = *pan;
+        }
+        player
+    }
+
+    pub fn mixer(&mut self) -> &mut Mixer<'s, CHANNELS> {
+        &mut self.mixer
+    }
+
+    // Order index and row about to be played
+    pub fn position(&self) -> (usize, u16) {
+        (self.order, self.row)
+    }
+
+    pub fn set_position(&mut self, order: usize) {
+        self.jump = Some((order, 0));
+        self.tick = 0;
+        self.next_row();
+    }
+
+    fn samples_per_tick(&self) -> u32 {
+        // There are tempo * 2 / 5 ticks a second
+        (MIX_RATE * 5 * 256) / (2 * u32::from(self.tempo))
+    }
+
+    fn pattern(&self) -> usize {
+        usize::from(self.song.orders.get(self.order).copied().unwrap_or(0))
+    }
+
+    fn tick(&mut self) {
+        if self.tick == 0 {
+            self.play_row();
+        } else {
+            for channel in 0..self.song.channels {
+                self.run_tick_effect(channel);
+            }
+        }
+
+        for channel in 0..self.song.channels {
+            self.update_voice(channel);
+        }
+
+        self.tick += 1;
+        if self.tick >= self.speed {
+            self.tick = 0;
+            self.next_row();
+        }
+    }
+
+    fn next_row(&mut self) {
+        if let Some((order, row)) = self.jump.take() {
+            self.order = if order < self.song.orders.len() {
+                order
+            } else {
+                self.song.restart
+            };
+            let pattern = self.pattern();
+            self.row = row.min(self.song.pattern_rows(pattern).saturating_sub(1));
+            self.row_offset = self.song.skip_rows(pattern, self.row, &mut self.cells);
+            return;
+        }
+
+        self.row += 1;
+        if self.row >= self.song.pattern_rows(self.pattern()) {
+            self.row = 0;
+            self.row_offset = 0;
+            self.order += 1;
+            if self.order >= self.song.orders.len() {
+                self.order = self.song.restart;
+            }
+        }
+    }
+
+    fn play_row(&mut self) {
+        let pattern = self.pattern();
+        let mut cells = mem::take(&mut self.cells);
+        self.row_offset = self.song.decode_row(pattern, self.row_offset, &mut cells);
+        for (channel, cell) in cells.iter().enumerate() {
+            self.play_cell(channel, cell);
+        }
+        self.cells = cells;
+    }
+
+    fn play_cell(&mut self, channel: usize, cell: &Cell) {
+        let song = self.song;
+        let tone_porta = cell.effect == 3 || cell.effect == 5 || cell.volume >> 4 == 0xF;
+        let state = &mut self.channels[channel];
+        state.effect = cell.effect;
+        state.param = cell.param;
+        state.volume_column = cell.volume;
+        state.arpeggio = None;
+
+        // An instrument on its own resets the volume
+        if cell.instrument != 0 {
+            state.instrument = cell.instrument;
+            let note = if (1..=96).contains(&cell.note) {
+                cell.note
+            } else {
+                (state.note + 1).clamp(1, 96) as u8
+            };
+            if let Some(sample) = song.find_sample(cell.instrument, note) {
+                state.volume = sample.volume;
+                if song.format == Format::FastTracker {
+                    state.pan = sample.pan;
+                }
+            }
+        }
+
+        if cell.note == NOTE_OFF {
+            self.mixer.stop(channel);
+        } else if cell.note != 0 {
+            if let Some(sample) = song.find_sample(state.instrument, cell.note) {
+                state.note = i32::from(cell.note) - 1 + i32::from(sample.relative_note);
+                state.finetune = i32::from(sample.finetune);
+                state.target_period = song.note_period(state.note, state.finetune);
+                if !tone_porta || !self.mixer.is_playing(channel) {
+                    state.period = state.target_period;
+                    state.vibrato_position = 0;
+                    let mut mixer_sample = Sample::new(&sample.data, 8363);
+                    if let Some(loop_start) = sample.loop_start {
+                        mixer_sample = mixer_sample.with_loop(loop_start);
+                    }
+                    self.mixer.play(channel, mixer_sample);
+                    if cell.effect == 9 {
+                        self.mixer
+                            .set_position(channel, usize::from(cell.param) * 256);
+                    }
+                }
+            }
+        }
+
+        self.run_row_volume_column(channel);
+        self.run_row_effect(channel);
+    }
+
+    fn run_row_volume_column(&mut self, channel: usize) {
+        let state = &mut self.channels[channel];
+        let value = state.volume_column;
+        match value >> 4 {
+            0x1..=0x4 => state.volume = value - 0x10,
+            0x5 if value == 0x50 => state.volume = MAX_VOLUME,
+            0x8 => state.slide_volume(value & 0xF),
+            0x9 => state.slide_volume(value << 4),
+            0xC => state.pan = ((i16::from(value & 0xF) * 16 - 128) / 2) as i8,
+            0xF if value & 0xF != 0 => state.tone_porta_speed = value << 4,
+            _ => (),
+        }
+    }
+
+    fn run_row_effect(&mut self, channel: usize) {
+        let scale = self.song.period_scale();
+        let state = &mut self.channels[channel];
+        let param = state.param;
+        let (x, y) = (param >> 4, param & 0xF);
+        match state.effect {
+            0x0 if param != 0 => state.arpeggio = Some((i32::from(x), i32::from(y))),
+            0x1 | 0x2 if param != 0 => state.porta_speed = param,
+            0x3 if param != 0 => state.tone_porta_speed = param,
+            0x4 => {
+                if x != 0 {
+                    state.vibrato_speed = x;
+                }
+                if y != 0 {
+                    state.vibrato_depth = y;
+                }
+            }
+            0x5 | 0x6 | 0xA if param != 0 => state.volume_slide = param,
+            0x8 => state.pan = ((i16::from(param) - 128) / 2).clamp(-64, 64) as i8,
+            0xB => self.jump = Some((usize::from(param), 0)),
+            0xC => state.volume = param.min(MAX_VOLUME),
+            0xD => {
+                let order = match self.jump {
+                    Some((order, _)) => order,
+                    None => self.order + 1,
+                };
+                self.jump = Some((order, u16::from(x) * 10 + u16::from(y)));
+            }
+            0xE => match x {
+                0x1 => state.period -= i32::from(y) * scale,
+                0x2 => state.period += i32::from(y) * scale,
+                0xA => state.slide_volume(y << 4),
+                0xB => state.slide_volume(y),
+                0xC if y == 0 => state.volume = 0,
+                _ => (),
+            },
+            0xF if param != 0 => {
+                if param < 32 {
+                    self.speed = param;
+                } else {
+                    self.tempo = param;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn run_tick_effect(&mut self, channel: usize) {
+        let scale = self.song.period_scale();
+        let tick = self.tick;
+        let state = &mut self.channels[channel];
+
+        let value = state.volume_column;
+        match value >> 4 {
+            0x6 => state.slide_volume(value & 0xF),
+            0x7 => state.slide_volume(value << 4),
+            0xF => state.tone_porta(scale),
+            _ => (),
+        }
+
+        let (x, y) = (state.param >> 4, state.param & 0xF);
+        match state.effect {
+            0x1 => state.period -= i32::from(state.porta_speed) * scale,
+            0x2 => state.period += i32::from(state.porta_speed) * scale,
+            0x3 => state.tone_porta(scale),
+            0x4 => state.vibrato_position = (state.vibrato_position + state.vibrato_speed) & 63,
+            0x5 => {
+                state.tone_porta(scale);
+                state.slide_volume(state.volume_slide);
+            }
+            0x6 => {
+                state.vibrato_position = (state.vibrato_position + state.vibrato_speed) & 63;
+                state.slide_volume(state.volume_slide);
+            }
+            0xA => state.slide_volume(state.volume_slide),
+            0xE if x == 0xC && y == tick => state.volume = 0,
+            _ => (),
+        }
+
+        let (min_period, max_period) = self.song.period_range();
+        state.period = state.period.clamp(min_period, max_period);
+    }
+
+    fn update_voice(&mut self, channel: usize) {
+        let song = self.song;
+        let scale = song.period_scale();
+        let state = &self.channels[channel];
+        let period = match state.arpeggio {
+            Some((x, y)) => {
+                let offset = match self.tick % 3 {
+                    0 => 0,
+                    1 => x,
+                    _ => y,
+                };
+                song.note_period(state.note + offset, state.finetune)
+            }
+            None if state.effect == 0x4 || state.effect == 0x6 => {
+                state.period + state.vibrato_offset(scale)
+            }
+            None => state.period,
+        };
+        self.mixer
+            .set_frequency(channel, song.period_frequency(period));
+        self.mixer.set_volume(channel, state.volume);
+        self.mixer
+            .set_pan(channel, state.pan.clamp(-MAX_PAN, MAX_PAN));
+    }
+}
+
+impl<'s, const CHANNELS: usize> SoundSource for Player<'s, CHANNELS> {
+    fn mix(&mut self, left: &mut [i8], right: &mut [i8]) {
+        let len = left.len();
+        let mut done = 0;
+        while done < len {
+            if self.tick_samples_left < 256 {
+                self.tick();
+                self.tick_samples_left += self.samples_per_tick();
+            }
+            let count = min((self.tick_samples_left >> 8) as usize, len - done);
+            self.mixer.mix(
+                &mut left[done..done + count],
+                &mut right[done..done + count],
+            );
+            done += count;
+            self.tick_samples_left -= (count as u32) << 8;
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use super::{
+    read_bytes, read_u16_be, read_u8, Cell, Format, FrequencyMode, Instrument, Pattern, Song,
+    TrackerError, TrackerSample,
+};
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::slice;
+
+const SAMPLE_COUNT: usize = 31;
+const ORDER_OFFSET: usize = 952;
+const SIGNATURE_OFFSET: usize = 1080;
+const PATTERN_OFFSET: usize = 1084;
+
+fn channel_count(signature: &[u8]) -> Option<usize> {
+    match signature {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"6CHN" => Some(6),
+        b"8CHN" | b"OCTA" | b"CD81" | b"FLT8" => Some(8),
+        [tens @ b'0'..=b'9', ones @ b'0'..=b'9', b'C', b'H'] => {
+            Some(usize::from(tens - b'0') * 10 + usize::from(ones - b'0'))
+        }
+        [count @ b'1'..=b'9', b'C', b'H', b'N'] => Some(usize::from(count - b'0')),
+        _ => None,
+    }
+}
+
+pub(super) fn parse(data: &'static [u8]) -> Result<Song, TrackerError> {
+    let channels =
+        channel_count(read_bytes(data, SIGNATURE_OFFSET, 4)?).ok_or(TrackerError::BadSignature)?;
+    if channels == 0 || channels > 32 {
+        return Err(TrackerError::BadChannelCount(channels));
+    }
+
+    let song_length = usize::from(read_u8(data, ORDER_OFFSET - 2)?).clamp(1, 128);
+    let restart = usize::from(read_u8(data, ORDER_OFFSET - 1)?);
+    let all_orders = read_bytes(data, ORDER_OFFSET, 128)?;
+    let pattern_count = usize::from(*all_orders.iter().max().unwrap()) + 1;
+    let orders = all_orders[..song_length].to_vec();
+
+    let pattern_size = 64 * channels * 4;
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for pattern in 0..pattern_count {
+        patterns.push(Pattern {
+            rows: 64,
+            data: read_bytes(data, PATTERN_OFFSET + pattern * pattern_size, pattern_size)?,
+        });
+    }
+
+    let mut sample_offset = PATTERN_OFFSET + pattern_count * pattern_size;
+    let mut samples = Vec::with_capacity(SAMPLE_COUNT);
+    let mut instruments = Vec::with_capacity(SAMPLE_COUNT);
+    for sample in 0..SAMPLE_COUNT {
+        let header = 20 + sample * 30;
+        let length = usize::from(read_u16_be(data, header + 22)?) * 2;
+        let finetune = ((read_u8(data, header + 24)? << 4) as i8) >> 4; // Signed nibble
+        let volume = read_u8(data, header + 25)?.min(64);
+        let loop_start = usize::from(read_u16_be(data, header + 26)?) * 2;
+        let loop_length = usize::from(read_u16_be(data, header + 28)?) * 2;
+
+        // Some files end early in the last sample
+        let start = sample_offset.min(data.len());
+        let bytes = &data[start..(start + length).min(data.len())];
+        let mut pcm = unsafe { slice::from_raw_parts(bytes.as_ptr() as *const i8, bytes.len()) };
+        sample_offset += length;
+
+        let loop_start = if loop_length > 2 && loop_start < pcm.len() {
+            pcm = &pcm[..(loop_start + loop_length).min(pcm.len())];
+            Some(loop_start)
+        } else {
+            None
+        };
+
+        instruments.push(Instrument {
+            sample_map: [0; 96],
+            first_sample: samples.len(),
+            sample_count: 1,
+        });
+        samples.push(TrackerSample {
+            data: Cow::Borrowed(pcm),
+            loop_start,
+            volume,
+            finetune: finetune * 16,
+            relative_note: 0,
+            pan: 0,
+        });
+    }
+
+    // Amiga channels go left, right, right, left
+    let default_pan = (0..channels)
+        .map(|channel| if (channel + 1) & 2 == 0 { -48 } else { 48 })
+        .collect();
+
+    Ok(Song {
+        format: Format::ProTracker,
+        frequency_mode: FrequencyMode::Amiga,
+        channels,
+        orders,
+        restart: if restart < song_length { restart } else { 0 },
+        patterns,
+        instruments,
+        samples,
+        initial_speed: 6,
+        initial_tempo: 125,
+        default_pan,
+    })
+}
+
+fn period_to_note(song: &Song, period: i32) -> u8 {
+    if period == 0 {
+        return 0;
+    }
+    let note = (0..96)
+        .min_by_key(|note| (song.note_period(*note, 0) - period).abs())
+        .unwrap();
+    note as u8 + 1
+}
+
+pub(super) fn decode_row(song: &Song, data: &[u8], offset: usize, cells: &mut [Cell]) -> usize {
+    let row_size = song.channels * 4;
+    let row = match data.get(offset..offset + row_size) {
+        Some(row) => row,
+        None => {
+            cells.fill(Cell::default());
+            return offset;
+        }
+    };
+    for (cell, raw) in cells.iter_mut().zip(row.chunks_exact(4)) {
+        let period = (i32::from(raw[0] & 0xF) << 8) | i32::from(raw[1]);
+        *cell = Cell {
+            note: period_to_note(song, period),
+            instrument: (raw[0] & 0xF0) | (raw[2] >> 4),
+            volume: 0,
+            effect: raw[2] & 0xF,
+            param: raw[3],
+        };
+    }
+    offset + row_size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    // Two 4 channel patterns, the first sample is 8 bytes with a loop over bytes 2..6
+    fn module(signature: &[u8; 4]) -> Vec<u8> {
+        let mut data = vec![0; PATTERN_OFFSET + 2 * 1024 + 8];
+        data[20 + 22..20 + 30].copy_from_slice(&[0, 4, 0xF, 70, 0, 1, 0, 2]);
+        data[ORDER_OFFSET - 2] = 2;
+        data[ORDER_OFFSET..ORDER_OFFSET + 2].copy_from_slice(&[0, 1]);
+        data[SIGNATURE_OFFSET..PATTERN_OFFSET].copy_from_slice(signature);
+        // C-2 with instrument 0x12 and effect C20 on the second channel of the first row
+        data[PATTERN_OFFSET + 4..PATTERN_OFFSET + 8].copy_from_slice(&[0x11, 0xAC, 0x2C, 0x20]);
+        let samples = data.len() - 8;
+        data[samples..].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        data
+    }
+
+    fn parse_module(data: Vec<u8>) -> Result<Song, TrackerError> {
+        parse(Vec::leak(data))
+    }
+
+    #[test]
+    fn header() {
+        let song = parse_module(module(b"M.K.")).unwrap();
+        assert_eq!(song.channels, 4);
+        assert_eq!(song.orders, [0, 1]);
+        assert_eq!(song.restart, 0);
+        assert_eq!(song.patterns.len(), 2);
+        assert_eq!(song.instruments.len(), SAMPLE_COUNT);
+        assert_eq!(song.default_pan, [-48, 48, 48, -48]);
+
+        let sample = &song.samples[0];
+        assert_eq!(&*sample.data, &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(sample.loop_start, Some(2));
+        assert_eq!(sample.volume, 64);
+        assert_eq!(sample.finetune, -16);
+        assert!(song.samples[1].data.is_empty());
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(channel_count(b"6CHN"), Some(6));
+        assert_eq!(channel_count(b"FLT8"), Some(8));
+        assert_eq!(channel_count(b"12CH"), Some(12));
+        assert_eq!(channel_count(b"2CHN"), Some(2));
+        assert_eq!(channel_count(b"M.K!"), None);
+
+        let song = parse_module(module(b"2CHN")).unwrap();
+        assert_eq!(song.channels, 2);
+        assert!(matches!(
+            parse_module(module(b"XXXX")),
+            Err(TrackerError::BadSignature)
+        ));
+        assert!(matches!(
+            parse_module(module(b"33CH")),
+            Err(TrackerError::BadChannelCount(33))
+        ));
+        let mut truncated = module(b"M.K.");
+        truncated.truncate(PATTERN_OFFSET + 1024);
+        assert!(matches!(
+            parse_module(truncated),
+            Err(TrackerError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn notes() {
+        let song = parse_module(module(b"M.K.")).unwrap();
+        assert_eq!(period_to_note(&song, 0), 0);
+        assert_eq!(period_to_note(&song, 428), 49);
+        assert_eq!(period_to_note(&song, 430), 49);
+        assert_eq!(period_to_note(&song, 856), 37);
+        assert_eq!(period_to_note(&song, 214), 61);
+    }
+
+    #[test]
+    fn rows() {
+        let song = parse_module(module(b"M.K.")).unwrap();
+        let data = song.patterns[0].data;
+        let mut cells = [Cell::default(); 4];
+        assert_eq!(decode_row(&song, data, 0, &mut cells), 16);
+        assert_eq!(cells[0].note, 0);
+        assert_eq!(cells[1].note, 49);
+        assert_eq!(cells[1].instrument, 0x12);
+        assert_eq!(cells[1].effect, 0xC);
+        assert_eq!(cells[1].param, 0x20);
+
+        assert_eq!(decode_row(&song, data, 1024, &mut cells), 1024);
+        assert!(cells
+            .iter()
+            .all(|cell| cell.note == 0 && cell.instrument == 0));
+    }
+}
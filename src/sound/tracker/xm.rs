@@ -0,0 +1,311 @@
+use super::{
+    read_bytes, read_u16_le, read_u32_le, read_u8, Cell, Format, FrequencyMode, Instrument,
+    Pattern, Song, TrackerError, TrackerSample,
+};
+use alloc::borrow::Cow;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+const SIGNATURE: &[u8] = b"Extended Module: ";
+const HEADER_OFFSET: usize = 60;
+const SAMPLE_HEADER_SIZE: usize = 40;
+
+// Samples are stored as deltas, 16 bit ones are cut down to 8 bits for the mixer
+fn decode_sample(raw: &[u8], sixteen_bit: bool) -> Vec<i8> {
+    if sixteen_bit {
+        let mut value = 0i16;
+        raw.chunks_exact(2)
+            .map(|delta| {
+                value = value.wrapping_add(i16::from_le_bytes(delta.try_into().unwrap()));
+                (value >> 8) as i8
+            })
+            .collect()
+    } else {
+        let mut value = 0i8;
+        raw.iter()
+            .map(|delta| {
+                value = value.wrapping_add(*delta as i8);
+                value
+            })
+            .collect()
+    }
+}
+
+pub(super) fn parse(data: &'static [u8]) -> Result<Song, TrackerError> {
+    if read_bytes(data, 0, SIGNATURE.len())? != SIGNATURE {
+        return Err(TrackerError::BadSignature);
+    }
+    let version = read_u16_le(data, 58)?;
+    if version != 0x104 {
+        return Err(TrackerError::UnsupportedVersion(version));
+    }
+
+    let header_size = read_u32_le(data, HEADER_OFFSET)? as usize;
+    let song_length = usize::from(read_u16_le(data, 64)?).clamp(1, 256);
+    let restart = usize::from(read_u16_le(data, 66)?);
+    let channels = usize::from(read_u16_le(data, 68)?);
+    let pattern_count = usize::from(read_u16_le(data, 70)?);
+    let instrument_count = usize::from(read_u16_le(data, 72)?);
+    let flags = read_u16_le(data, 74)?;
+    let initial_speed = read_u16_le(data, 76)? as u8;
+    let initial_tempo = read_u16_le(data, 78)? as u8;
+    let orders = read_bytes(data, 80, song_length)?.to_vec();
+
+    if channels == 0 || channels > 32 {
+        return Err(TrackerError::BadChannelCount(channels));
+    }
+
+    let mut offset = HEADER_OFFSET + header_size;
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        let pattern_header_size = read_u32_le(data, offset)? as usize;
+        let rows = read_u16_le(data, offset + 5)?;
+        let packed_size = usize::from(read_u16_le(data, offset + 7)?);
+        offset += pattern_header_size;
+        patterns.push(Pattern {
+            rows,
+            data: read_bytes(data, offset, packed_size)?,
+        });
+        offset += packed_size;
+    }
+
+    let mut instruments = Vec::with_capacity(instrument_count);
+    let mut samples = Vec::new();
+    for _ in 0..instrument_count {
+        let instrument_size = read_u32_le(data, offset)? as usize;
+        let sample_count = usize::from(read_u16_le(data, offset + 27)?);
+        let mut sample_map = [0; 96];
+        if sample_count > 0 {
+            sample_map.copy_from_slice(read_bytes(data, offset + 33, 96)?);
+        }
+        instruments.push(Instrument {
+            sample_map,
+            first_sample: samples.len(),
+            sample_count,
+        });
+        offset += instrument_size;
+
+        let headers_begin = offset;
+        offset += sample_count * SAMPLE_HEADER_SIZE;
+        for sample in 0..sample_count {
+            let header = headers_begin + sample * SAMPLE_HEADER_SIZE;
+            let length = read_u32_le(data, header)? as usize;
+            let loop_start = read_u32_le(data, header + 4)? as usize;
+            let loop_length = read_u32_le(data, header + 8)? as usize;
+            let volume = read_u8(data, header + 12)?.min(64);
+            let finetune = read_u8(data, header + 13)? as i8;
+            let sample_type = read_u8(data, header + 14)?;
+            let pan = read_u8(data, header + 15)?;
+            let relative_note = read_u8(data, header + 16)? as i8;
+
+            let sixteen_bit = sample_type & 0x10 != 0;
+            let pcm = decode_sample(read_bytes(data, offset, length)?, sixteen_bit);
+            offset += length;
+
+            let (loop_start, loop_length) = if sixteen_bit {
+                (loop_start / 2, loop_length / 2)
+            } else {
+                (loop_start, loop_length)
+            };
+            // Ping pong loops are played as forward loops
+            let loop_start = if sample_type & 3 != 0 && loop_length > 0 && loop_start < pcm.len() {
+                Some(loop_start)
+            } else {
+                None
+            };
+            let mut pcm = pcm;
+            if let Some(loop_start) = loop_start {
+                pcm.truncate(loop_start + loop_length);
+            }
+
+            samples.push(TrackerSample {
+                data: Cow::Owned(pcm),
+                loop_start,
+                volume,
+                finetune,
+                relative_note,
+                pan: ((i16::from(pan) - 128) / 2) as i8,
+            });
+        }
+    }
+
+    Ok(Song {
+        format: Format::FastTracker,
+        frequency_mode: if flags & 1 != 0 {
+            FrequencyMode::Linear
+        } else {
+            FrequencyMode::Amiga
+        },
+        channels,
+        orders,
+        restart: if restart < song_length { restart } else { 0 },
+        patterns,
+        instruments,
+        samples,
+        initial_speed: initial_speed.max(1),
+        initial_tempo: initial_tempo.max(32),
+        default_pan: vec![0; channels],
+    })
+}
+
+pub(super) fn decode_row(data: &[u8], mut offset: usize, cells: &mut [Cell]) -> usize {
+    // An empty pattern has no data at all
+    let mut next = || {
+        let byte = data.get(offset).copied().unwrap_or(0);
+        offset += 1;
+        byte
+    };
+    for cell in cells.iter_mut() {
+        let first = next();
+        let mask = if first & 0x80 != 0 { first } else { 0x1F };
+        *cell = Cell {
+            note: if first & 0x80 == 0 {
+                first
+            } else if mask & 1 != 0 {
+                next()
+            } else {
+                0
+            },
+            instrument: if mask & 2 != 0 { next() } else { 0 },
+            volume: if mask & 4 != 0 { next() } else { 0 },
+            effect: if mask & 8 != 0 { next() } else { 0 },
+            param: if mask & 0x10 != 0 { next() } else { 0 },
+        };
+    }
+    offset.min(data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sound::tracker::NOTE_OFF;
+
+    // The first row is an unpacked cell and an empty one, the second a note off with an
+    // instrument and an effect with its param
+    const PATTERN: [u8; 12] = [49, 1, 0x40, 0xF, 6, 0x80, 0x83, NOTE_OFF, 2, 0x98, 0xA, 0xF];
+
+    // Two channels, one two row pattern and one instrument with a looping 16 bit sample
+    fn module() -> Vec<u8> {
+        let mut data = SIGNATURE.to_vec();
+        data.resize(58, 0);
+        data.extend_from_slice(&0x104u16.to_le_bytes());
+        data.extend_from_slice(&276u32.to_le_bytes());
+        for field in [1u16, 0, 2, 1, 1, 1, 0, 10].iter() {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.resize(HEADER_OFFSET + 276, 0);
+
+        data.extend_from_slice(&9u32.to_le_bytes());
+        data.push(0);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&(PATTERN.len() as u16).to_le_bytes());
+        data.extend_from_slice(&PATTERN);
+
+        let instrument = data.len();
+        data.extend_from_slice(&263u32.to_le_bytes());
+        data.resize(instrument + 27, 0);
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.resize(instrument + 263, 0);
+
+        for field in [8u32, 2, 4].iter() {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&[80, 0xF0, 0x11, 0, 12]);
+        data.resize(data.len() + 23, 0);
+        for delta in [0x100i16, 0x100, -0x200, 0].iter() {
+            data.extend_from_slice(&delta.to_le_bytes());
+        }
+        data
+    }
+
+    fn parse_module(data: Vec<u8>) -> Result<Song, TrackerError> {
+        parse(Vec::leak(data))
+    }
+
+    #[test]
+    fn header() {
+        let song = parse_module(module()).unwrap();
+        assert_eq!(song.frequency_mode, FrequencyMode::Linear);
+        assert_eq!(song.channels, 2);
+        assert_eq!(song.orders, [0]);
+        assert_eq!(song.initial_speed, 1);
+        assert_eq!(song.initial_tempo, 32);
+        assert_eq!(song.patterns.len(), 1);
+        assert_eq!(song.patterns[0].rows, 2);
+        assert_eq!(song.patterns[0].data, &PATTERN);
+
+        assert_eq!(song.instruments.len(), 1);
+        assert_eq!(song.instruments[0].sample_count, 1);
+        let sample = &song.samples[0];
+        assert_eq!(&*sample.data, &[1, 2, 0]);
+        assert_eq!(sample.loop_start, Some(1));
+        assert_eq!(sample.volume, 64);
+        assert_eq!(sample.finetune, -16);
+        assert_eq!(sample.relative_note, 12);
+        assert_eq!(sample.pan, -64);
+    }
+
+    #[test]
+    fn bad_headers() {
+        let mut data = module();
+        data[0] = b'e';
+        assert!(matches!(
+            parse_module(data),
+            Err(TrackerError::BadSignature)
+        ));
+
+        let mut data = module();
+        data[58] = 3;
+        assert!(matches!(
+            parse_module(data),
+            Err(TrackerError::UnsupportedVersion(0x103))
+        ));
+
+        let mut data = module();
+        data[68] = 0;
+        assert!(matches!(
+            parse_module(data),
+            Err(TrackerError::BadChannelCount(0))
+        ));
+
+        let mut data = module();
+        data.truncate(data.len() - 2);
+        assert!(matches!(parse_module(data), Err(TrackerError::Truncated)));
+    }
+
+    #[test]
+    fn samples() {
+        assert_eq!(decode_sample(&[1, 1, 0xFE, 0x80], false), [1, 2, 0, -128]);
+        assert_eq!(decode_sample(&[0, 0x80, 0, 0x80, 1], true), [-128, 0]);
+    }
+
+    #[test]
+    fn rows() {
+        let mut cells = [Cell::default(); 2];
+        assert_eq!(decode_row(&PATTERN, 0, &mut cells), 6);
+        assert_eq!(
+            (cells[0].note, cells[0].instrument, cells[0].volume),
+            (49, 1, 0x40)
+        );
+        assert_eq!((cells[0].effect, cells[0].param), (0xF, 6));
+        assert_eq!((cells[1].note, cells[1].instrument), (0, 0));
+
+        assert_eq!(decode_row(&PATTERN, 6, &mut cells), PATTERN.len());
+        assert_eq!((cells[0].note, cells[0].instrument), (NOTE_OFF, 2));
+        assert_eq!((cells[0].volume, cells[0].effect), (0, 0));
+        assert_eq!(
+            (cells[1].note, cells[1].effect, cells[1].param),
+            (0, 0xA, 0xF)
+        );
+
+        // Empty patterns have no data and reading past the end gives empty cells
+        assert_eq!(decode_row(&[], 0, &mut cells), 0);
+        assert_eq!(cells[0].note, 0);
+        assert_eq!(
+            decode_row(&PATTERN, PATTERN.len(), &mut cells),
+            PATTERN.len()
+        );
+        assert_eq!((cells[1].effect, cells[1].param), (0, 0));
+    }
+}
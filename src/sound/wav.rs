@@ -0,0 +1,325 @@
+use super::adpcm::{self, AdpcmStream};
+use super::MIX_RATE;
+use crate::file::RomFile;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IMA_ADPCM: u16 = 0x11;
+
+#[derive(Debug)]
+pub enum WavError {
+    NotRiff,
+    Truncated,
+    #[allow(dead_code)]
+    MissingChunk(&'static [u8; 4]),
+    #[allow(dead_code)]
+    Unsupported {
+        format: u16,
+        channels: u16,
+        bits: u16,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavEncoding {
+    Pcm8,
+    Pcm16,
+    ImaAdpcm { block_align: u16 },
+}
+
+pub struct WavFile {
+    encoding: WavEncoding,
+    channels: u16,
+    rate: u32,
+    data: &'static [u8],
+}
+
+fn find_chunk(mut chunks: &'static [u8], id: &'static [u8; 4]) -> Result<&'static [u8], WavError> {
+    while chunks.len() >= 8 {
+        let size = u32::from_le_bytes(chunks[4..8].try_into().unwrap()) as usize;
+        let body = chunks.get(8..8 + size).ok_or(WavError::Truncated)?;
+        if &chunks[0..4] == id {
+            return Ok(body);
+        }
+        let padded = 8 + size + (size & 1);
+        chunks = chunks.get(padded..).unwrap_or(&[]);
+    }
+    Err(WavError::MissingChunk(id))
+}
+
+#[allow(dead_code)]
+impl WavFile {
+    pub fn parse(file: &RomFile) -> Result<Self, WavError> {
+        Self::from_bytes(file.as_bytes())
+    }
+
+    fn from_bytes(bytes: &'static [u8]) -> Result<Self, WavError> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(WavError::NotRiff);
+        }
+        let chunks = &bytes[12..];
+        let fmt = find_chunk(chunks, b"fmt ")?;
+        if fmt.len() < 16 {
+            return Err(WavError::Truncated);
+        }
+        let field = |offset: usize| u16::from_le_bytes(fmt[offset..offset + 2].try_into().unwrap());
+        let format = field(0);
+        let channels = field(2);
+        let rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+        let block_align = field(12);
+        let bits = field(14);
+
+        let encoding = match (format, bits) {
+            (FORMAT_PCM, 8) => WavEncoding::Pcm8,
+            (FORMAT_PCM, 16) => WavEncoding::Pcm16,
+            (FORMAT_IMA_ADPCM, 4) if channels == 1 && block_align > 4 => {
+                WavEncoding::ImaAdpcm { block_align }
+            }
+            _ => {
+                return Err(WavError::Unsupported {
+                    format,
+                    channels,
+                    bits,
+                })
+            }
+        };
+        if channels == 0 || channels > 2 || rate == 0 {
+            return Err(WavError::Unsupported {
+                format,
+                channels,
+                bits,
+            });
+        }
+
+        Ok(WavFile {
+            encoding,
+            channels,
+            rate,
+            data: find_chunk(chunks, b"data")?,
+        })
+    }
+
+    pub fn encoding(&self) -> WavEncoding {
+        self.encoding
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn pcm_frames(&self) -> usize {
+        let bytes_per_sample = match self.encoding {
+            WavEncoding::Pcm16 => 2,
+            _ => 1,
+        };
+        self.data.len() / (bytes_per_sample * usize::from(self.channels))
+    }
+
+    // Mono 8 bit signed value of a pcm frame
+    fn pcm_frame(&self, frame: usize) -> i32 {
+        let channels = usize::from(self.channels);
+        (0..channels)
+            .map(|channel| {
+                let index = frame * channels + channel;
+                match self.encoding {
+                    WavEncoding::Pcm8 => i32::from(self.data[index]) - 128, // 8 bit wavs are unsigned
+                    _ => {
+                        let raw = self.data[index * 2..index * 2 + 2].try_into().unwrap();
+                        i32::from(i16::from_le_bytes(raw)) >> 8
+                    }
+                }
+            })
+            .sum::<i32>()
+            / channels as i32
+    }
+
+    // Decodes the whole file to mono 8 bit signed samples at MIX_RATE
+    pub fn decode(&self) -> Vec<i8> {
+        let (frames, source): (usize, Vec<i8>) = match self.encoding {
+            WavEncoding::ImaAdpcm { block_align } => {
+                let block_align = usize::from(block_align);
+                let blocks = self.data.len().div_ceil(block_align);
+                let mut decoded = vec![0; blocks * adpcm::samples_per_block(block_align)];
+                let mut len = 0;
+                for block in self.data.chunks(block_align) {
+                    len += adpcm::decode_block(block, &mut decoded[len..]);
+                }
+                decoded.truncate(len);
+                (len, decoded)
+            }
+            _ => (self.pcm_frames(), Vec::new()),
+        };
+        if frames == 0 {
+            return Vec::new();
+        }
+
+        let frame = |index: usize| -> i32 {
+            if source.is_empty() {
+                self.pcm_frame(index)
+            } else {
+                i32::from(source[index])
+            }
+        };
+
+        // Linear interpolation in 16.16 fixed point
+        let step = (u64::from(self.rate) << 16) / u64::from(MIX_RATE);
+        let out_len = ((frames as u64) << 16) / step;
+        (0..out_len)
+            .map(|index| {
+                let position = index * step;
+                let whole = (position >> 16) as usize;
+                let fraction = (position & 0xFFFF) as i32;
+                let current = frame(whole);
+                let next = if whole + 1 < frames {
+                    frame(whole + 1)
+                } else {
+                    current
+                };
+                (current + (((next - current) * fraction) >> 16)) as i8
+            })
+            .collect()
+    }
+
+    // Streams the file without decoding it up front, only for ima adpcm files
+    pub fn stream(&self) -> Option<AdpcmStream> {
+        match self.encoding {
+            WavEncoding::ImaAdpcm { block_align } => Some(AdpcmStream::new(
+                self.data,
+                usize::from(block_align),
+                self.rate,
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+        out.extend_from_slice(id);
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        if !body.len().is_multiple_of(2) {
+            out.push(0);
+        }
+    }
+
+    // An odd sized chunk sits between fmt and data to check the padding is skipped
+    fn wav(
+        format: u16,
+        channels: u16,
+        rate: u32,
+        block_align: u16,
+        bits: u16,
+        data: &[u8],
+    ) -> &'static [u8] {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&format.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&rate.to_le_bytes());
+        fmt.extend_from_slice(&(rate * u32::from(block_align)).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+
+        let mut chunks = Vec::new();
+        chunk(&mut chunks, b"fmt ", &fmt);
+        chunk(&mut chunks, b"LIST", b"odd");
+        chunk(&mut chunks, b"data", data);
+
+        let mut out = b"RIFF".to_vec();
+        out.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+        out.extend_from_slice(&chunks);
+        Vec::leak(out)
+    }
+
+    #[test]
+    fn chunks() {
+        let file = wav(FORMAT_PCM, 1, MIX_RATE, 1, 8, &[1, 2, 3]);
+        let chunks = &file[12..];
+        assert_eq!(find_chunk(chunks, b"LIST").unwrap(), b"odd");
+        assert_eq!(find_chunk(chunks, b"data").unwrap(), &[1, 2, 3]);
+        assert!(matches!(
+            find_chunk(chunks, b"fact"),
+            Err(WavError::MissingChunk(b"fact"))
+        ));
+        assert!(matches!(
+            find_chunk(&chunks[..chunks.len() - 2], b"data"),
+            Err(WavError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn bad_files() {
+        assert!(matches!(
+            WavFile::from_bytes(b"RIFF\0\0\0\0AVI "),
+            Err(WavError::NotRiff)
+        ));
+        assert!(matches!(
+            WavFile::from_bytes(wav(3, 1, MIX_RATE, 4, 32, &[])),
+            Err(WavError::Unsupported { format: 3, .. })
+        ));
+        assert!(matches!(
+            WavFile::from_bytes(wav(FORMAT_PCM, 3, MIX_RATE, 3, 8, &[])),
+            Err(WavError::Unsupported { channels: 3, .. })
+        ));
+        assert!(matches!(
+            WavFile::from_bytes(wav(FORMAT_IMA_ADPCM, 2, MIX_RATE, 36, 4, &[])),
+            Err(WavError::Unsupported { channels: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn pcm() {
+        let mono =
+            WavFile::from_bytes(wav(FORMAT_PCM, 1, MIX_RATE, 1, 8, &[128, 255, 0, 64])).unwrap();
+        assert_eq!(mono.encoding(), WavEncoding::Pcm8);
+        assert!(mono.stream().is_none());
+        assert_eq!(mono.decode(), [0, 127, -128, -64]);
+
+        let mut data = Vec::new();
+        for sample in [0x1000i16, 0x3000, i16::MIN, i16::MIN, -0x100, 0x100].iter() {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        let stereo = WavFile::from_bytes(wav(FORMAT_PCM, 2, MIX_RATE, 4, 16, &data)).unwrap();
+        assert_eq!(stereo.encoding(), WavEncoding::Pcm16);
+        assert_eq!(stereo.channels(), 2);
+        assert_eq!(stereo.decode(), [32, -128, 0]);
+    }
+
+    #[test]
+    fn resampling() {
+        let half =
+            WavFile::from_bytes(wav(FORMAT_PCM, 1, MIX_RATE / 2, 1, 8, &[128, 192])).unwrap();
+        let decoded = half.decode();
+        assert_eq!(decoded.len(), 4);
+        assert_eq!((decoded[0], decoded[3]), (0, 64));
+        assert!(decoded.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let empty = WavFile::from_bytes(wav(FORMAT_PCM, 1, MIX_RATE, 1, 8, &[])).unwrap();
+        assert!(empty.decode().is_empty());
+    }
+
+    #[test]
+    fn adpcm_blocks() {
+        // A full 8 byte block and a 6 byte one at the end
+        let mut data = vec![0x00, 0x10, 20, 0, 0x21, 0x43, 0x65, 0x87];
+        data.extend_from_slice(&[0x00, 0xF0, 0, 0, 0x00, 0x00]);
+        let file = WavFile::from_bytes(wav(FORMAT_IMA_ADPCM, 1, MIX_RATE, 8, 4, &data)).unwrap();
+        assert_eq!(file.encoding(), WavEncoding::ImaAdpcm { block_align: 8 });
+        assert!(file.stream().is_some());
+
+        let decoded = file.decode();
+        assert_eq!(decoded.len(), 9 + 5);
+        assert_eq!(&decoded[..9], &[16, 16, 16, 16, 16, 16, 17, 17, 17]);
+        assert_eq!(decoded[9], -16);
+    }
+}
@@ -6,3 +6,14 @@ static mut TIMER_VALUE: u32 = 0;
 pub fn get_timer() -> u32 {
     unsafe { ptr::read_volatile(&TIMER_VALUE) }
 }
+
+// Runs with the irq master enable cleared, restoring it afterwards
+pub fn without_irq<R, F: FnOnce() -> R>(fun: F) -> R {
+    unsafe {
+        let master_enable = ptr::read_volatile(0x4000208 as *const u16);
+        ptr::write_volatile(0x4000208 as *mut u16, 0);
+        let ret = fun();
+        ptr::write_volatile(0x4000208 as *mut u16, master_enable);
+        ret
+    }
+}
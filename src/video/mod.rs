@@ -1,5 +1,5 @@
 use crate::fast_mem::FastAllocator;
-use crate::file::SeekFrom;
+use crate::io::{Seek, SeekFrom};
 use crate::util::get_timer;
 use crate::{println, RomFile};
 use alloc::boxed::Box;
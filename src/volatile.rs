@@ -1,5 +1,7 @@
 use core::cell::UnsafeCell;
+use core::ops;
 use core::ptr;
+use core::slice;
 use core::sync::atomic::compiler_fence;
 use core::sync::atomic::Ordering;
 
@@ -7,18 +9,42 @@ extern "C" {
     fn atomic_swap_byte(loc: *mut u8, value: u8) -> u8;
 }
 
-pub struct VolatileBool(UnsafeCell<bool>);
+#[repr(transparent)]
+pub struct VolatileCell<T: Copy>(UnsafeCell<T>);
 
-unsafe impl Sync for VolatileBool {}
+unsafe impl<T: Copy> Sync for VolatileCell<T> {}
 
-impl VolatileBool {
-    pub fn read(&self) -> bool {
+impl<T: Copy> VolatileCell<T> {
+    pub const fn new(value: T) -> Self {
+        VolatileCell(UnsafeCell::new(value))
+    }
+
+    pub fn read(&self) -> T {
         compiler_fence(Ordering::SeqCst);
         let ret = unsafe { ptr::read_volatile(self.0.get()) };
         compiler_fence(Ordering::SeqCst);
         ret
     }
 
+    pub fn write(&self, value: T) {
+        compiler_fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile(self.0.get(), value) }
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    // Not atomic, an irq can land between the read and the write
+    pub fn update<F: FnOnce(T) -> T>(&self, fun: F) -> T {
+        let value = fun(self.read());
+        self.write(value);
+        value
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        self.0.get()
+    }
+}
+
+impl VolatileCell<bool> {
     pub fn swap(&self, replace: bool) -> bool {
         compiler_fence(Ordering::SeqCst);
         let replace_num = u8::from(replace);
@@ -26,48 +52,133 @@ impl VolatileBool {
         compiler_fence(Ordering::SeqCst);
         ret
     }
+}
+
+pub type VolatileBool = VolatileCell<bool>;
+#[allow(dead_code)]
+pub type VolatileMutPtr<T> = VolatileCell<*mut T>;
+pub type VolatileUsize = VolatileCell<usize>;
+
+#[repr(transparent)]
+pub struct VolatileSlice<T: Copy>([VolatileCell<T>]);
+
+impl<T: Copy> VolatileSlice<T> {
+    /// # Safety
+    /// The region must be valid for volatile reads and writes of `len` `T`s for `'a`.
+    pub unsafe fn from_raw_parts<'a>(data: *mut T, len: usize) -> &'a Self {
+        unsafe { Self::from_cells(slice::from_raw_parts(data as *const VolatileCell<T>, len)) }
+    }
 
-    pub fn write(&self, value: bool) {
-        compiler_fence(Ordering::SeqCst);
-        unsafe { ptr::write_volatile(self.0.get(), value) }
-        compiler_fence(Ordering::SeqCst);
+    fn from_cells(cells: &[VolatileCell<T>]) -> &Self {
+        unsafe { &*(cells as *const [VolatileCell<T>] as *const Self) }
     }
 
-    pub const fn new(value: bool) -> Self {
-        VolatileBool(UnsafeCell::new(value))
+    pub fn len(&self) -> usize {
+        self.0.len()
     }
-}
 
-pub struct VolatileMutPtr<T>(UnsafeCell<*mut T>);
-unsafe impl<T> Sync for VolatileMutPtr<T> {}
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 
-impl<T> VolatileMutPtr<T> {
-    pub fn read(&self) -> *mut T {
-        unsafe { ptr::read_volatile(self.0.get()) }
+    pub fn get(&self, index: usize) -> Option<&VolatileCell<T>> {
+        self.0.get(index)
     }
 
-    pub fn write(&self, value: *mut T) {
-        unsafe { ptr::write_volatile(self.0.get(), value) }
+    pub fn read(&self, index: usize) -> T {
+        self.0[index].read()
     }
 
-    pub const fn new(value: *mut T) -> Self {
-        VolatileMutPtr(UnsafeCell::new(value))
+    pub fn write(&self, index: usize, value: T) {
+        self.0[index].write(value)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, VolatileCell<T>> {
+        self.0.iter()
+    }
+
+    pub fn fill(&self, value: T) {
+        for cell in self.iter() {
+            cell.write(value)
+        }
+    }
+
+    pub fn copy_from_slice(&self, src: &[T]) {
+        assert_eq!(self.len(), src.len(), "Slice length mismatch");
+        for (cell, value) in self.iter().zip(src) {
+            cell.write(*value)
+        }
+    }
+
+    pub fn copy_to_slice(&self, dest: &mut [T]) {
+        assert_eq!(self.len(), dest.len(), "Slice length mismatch");
+        for (value, cell) in dest.iter_mut().zip(self.iter()) {
+            *value = cell.read()
+        }
+    }
+
+    pub fn subslice(&self, range: ops::Range<usize>) -> &Self {
+        Self::from_cells(&self.0[range])
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        self.0.as_ptr() as *mut T
     }
 }
 
-pub struct VolatileUsize(UnsafeCell<usize>);
-unsafe impl Sync for VolatileUsize {}
+impl<T: Copy> ops::Index<usize> for VolatileSlice<T> {
+    type Output = VolatileCell<T>;
 
-impl VolatileUsize {
-    pub fn read(&self) -> usize {
-        unsafe { ptr::read_volatile(self.0.get()) }
+    fn index(&self, index: usize) -> &VolatileCell<T> {
+        &self.0[index]
     }
+}
 
-    pub fn write(&self, value: usize) {
-        unsafe { ptr::write_volatile(self.0.get(), value) }
+impl<'a, T: Copy> IntoIterator for &'a VolatileSlice<T> {
+    type Item = &'a VolatileCell<T>;
+    type IntoIter = slice::Iter<'a, VolatileCell<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
+}
 
-    pub const fn new(value: usize) -> Self {
-        VolatileUsize(UnsafeCell::new(value))
+#[repr(transparent)]
+pub struct VolatileArray<T: Copy, const N: usize>([VolatileCell<T>; N]);
+
+impl<T: Copy, const N: usize> VolatileArray<T, N> {
+    /// # Safety
+    /// `addr` must point to `N` `T`s valid for volatile access for the rest of the program.
+    pub unsafe fn at_address(addr: usize) -> &'static Self {
+        unsafe { &*(addr as *const Self) }
+    }
+}
+
+impl<T: Copy, const N: usize> ops::Deref for VolatileArray<T, N> {
+    type Target = VolatileSlice<T>;
+
+    fn deref(&self) -> &VolatileSlice<T> {
+        VolatileSlice::from_cells(&self.0)
     }
 }
+
+pub type PaletteRam = VolatileArray<u16, 0x200>;
+pub type Oam = VolatileArray<u16, 0x200>;
+pub type Vram = VolatileArray<u16, 0xC000>;
+
+#[allow(dead_code)]
+pub fn palette_ram() -> &'static PaletteRam {
+    unsafe { PaletteRam::at_address(0x5000000) }
+}
+
+#[allow(dead_code)]
+pub fn oam() -> &'static Oam {
+    unsafe { Oam::at_address(0x7000000) }
+}
+
+// Byte writes to vram are copied to both bytes of the halfword in bg vram and dropped in obj
+// vram, so it is only exposed as halfwords to keep them from happening by accident
+#[allow(dead_code)]
+pub fn vram() -> &'static Vram {
+    unsafe { Vram::at_address(0x6000000) }
+}
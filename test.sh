@@ -0,0 +1,3 @@
+#!/usr/bin/env bash
+python3 py/create_fs.py data data.bin
+rustup run nightly cargo test --lib
@@ -0,0 +1,414 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::process;
+use xmas_elf::sections::{SectionData, ShType};
+use xmas_elf::symbol_table::Entry;
+use xmas_elf::ElfFile;
+
+const ROM_BASE: u64 = 0x8000000;
+const MAX_ROM_SIZE: usize = 32 * 1024 * 1024;
+const MIN_ROM_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug)]
+enum Error {
+    Usage,
+    Io(io::Error),
+    Elf(&'static str),
+    MissingSection(&'static str),
+    MissingSymbol(&'static str),
+    NotInRom(&'static str, u64),
+    Overlap(&'static str, &'static str),
+    TooBig(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage => write!(f, "usage: makerom <elf> <rom>"),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Elf(error) => write!(f, "bad elf file: {}", error),
+            Error::MissingSection(name) => write!(f, "missing section {}", name),
+            Error::MissingSymbol(name) => write!(f, "missing symbol {}", name),
+            Error::NotInRom(name, address) => write!(f, "{} at {:#x} is not in rom", name, address),
+            Error::Overlap(name, other) => write!(f, "sections {} and {} overlap", name, other),
+            Error::TooBig(size) => {
+                write!(f, "rom is {} bytes, the limit is {}", size, MAX_ROM_SIZE)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<&'static str> for Error {
+    fn from(error: &'static str) -> Self {
+        Error::Elf(error)
+    }
+}
+
+struct Section<'a> {
+    name: &'static str,
+    address: u64,
+    data: &'a [u8],
+}
+
+fn find_section<'a>(elf: &ElfFile<'a>, name: &'static str) -> Result<Section<'a>, Error> {
+    // Section 0 is the null section, which has no name
+    for header in elf.section_iter().skip(1) {
+        if header.get_name(elf)? == name {
+            let data = if header.get_type()? == ShType::NoBits {
+                &[]
+            } else {
+                header.raw_data(elf)
+            };
+            return Ok(Section {
+                name,
+                address: header.address(),
+                data,
+            });
+        }
+    }
+    Err(Error::MissingSection(name))
+}
+
+fn find_symbol(elf: &ElfFile, name: &'static str) -> Result<u64, Error> {
+    for header in elf.section_iter() {
+        if let SectionData::SymbolTable32(symbols) = header.get_data(elf)? {
+            for symbol in symbols {
+                if symbol.get_name(elf)? == name {
+                    return Ok(symbol.value());
+                }
+            }
+        }
+    }
+    Err(Error::MissingSymbol(name))
+}
+
+fn rom_offset(name: &'static str, address: u64) -> Result<usize, Error> {
+    if address < ROM_BASE || address - ROM_BASE >= MAX_ROM_SIZE as u64 {
+        return Err(Error::NotInRom(name, address));
+    }
+    Ok((address - ROM_BASE) as usize)
+}
+
+fn align4(rom: &mut Vec<u8>) {
+    rom.resize((rom.len() + 3) & !3, 0);
+}
+
+// Each section goes at its address with the gaps between them zero filled
+fn place_sections(sections: &[Section]) -> Result<Vec<u8>, Error> {
+    let mut rom = Vec::new();
+    let mut placed: Vec<(&'static str, usize, usize)> = Vec::new();
+    for section in sections {
+        let begin = rom_offset(section.name, section.address)?;
+        let end = begin + section.data.len();
+        if end > MAX_ROM_SIZE {
+            return Err(Error::TooBig(end));
+        }
+        let overlap = placed
+            .iter()
+            .find(|(_, other_begin, other_end)| begin < *other_end && *other_begin < end);
+        if let Some((other, _, _)) = overlap {
+            return Err(Error::Overlap(section.name, other));
+        }
+        placed.push((section.name, begin, end));
+
+        if rom.len() < end {
+            rom.resize(end, 0);
+        }
+        rom[begin..end].copy_from_slice(section.data);
+    }
+    Ok(rom)
+}
+
+// Ram sections are appended and the startup code is told where through the word at `symbol`
+fn append_load_section(
+    rom: &mut Vec<u8>,
+    data: &[u8],
+    symbol: &'static str,
+    symbol_address: u64,
+) -> Result<(), Error> {
+    align4(rom);
+    let load_address = ROM_BASE as u32 + rom.len() as u32;
+    let location = rom_offset(symbol, symbol_address)?;
+    if location + 4 > rom.len() {
+        return Err(Error::NotInRom(symbol, symbol_address));
+    }
+    rom[location..location + 4].copy_from_slice(&load_address.to_le_bytes());
+    rom.extend_from_slice(data);
+    Ok(())
+}
+
+// Carts come in power of two sizes
+fn pad_rom(rom: &mut Vec<u8>) -> Result<(), Error> {
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(Error::TooBig(rom.len()));
+    }
+    let rounded_size = rom.len().next_power_of_two().max(MIN_ROM_SIZE);
+    rom.resize(rounded_size, 0);
+    Ok(())
+}
+
+// The complement check at 0xBD over the header from the title at 0xA0
+fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0xA0..0xBD]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_sub(*byte))
+        .wrapping_sub(0x19)
+}
+
+fn build_rom(elf: &ElfFile) -> Result<Vec<u8>, Error> {
+    let mut sections = Vec::new();
+    for (name, required) in &[("header", true), ("read_only", true), ("exidx", false)] {
+        match find_section(elf, name) {
+            Err(Error::MissingSection(_)) if !required => {}
+            section => sections.push(section?),
+        }
+    }
+    let mut rom = place_sections(&sections)?;
+
+    for (section, symbol) in &[
+        ("modify", "__load_modify_begin"),
+        ("fast_mem", "__load_fast_mem_begin"),
+    ] {
+        let data = find_section(elf, section)?.data;
+        append_load_section(&mut rom, data, symbol, find_symbol(elf, symbol)?)?;
+    }
+
+    pad_rom(&mut rom)?;
+    rom[0xBD] = header_checksum(&rom);
+    Ok(rom)
+}
+
+fn run() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    let (elf_path, rom_path) = match args.as_slice() {
+        [_, elf_path, rom_path] => (elf_path, rom_path),
+        _ => return Err(Error::Usage),
+    };
+
+    let elf_data = fs::read(elf_path)?;
+    let elf = ElfFile::new(&elf_data)?;
+    let rom = build_rom(&elf)?;
+    fs::write(rom_path, rom)?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("makerom: {}", error);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_SIZE: usize = 0xC0;
+
+    // A 32 bit arm elf with just the given progbits sections and symbols, plus the tables
+    // needed to find them by name
+    fn elf_file(sections: &[(&str, u32, &[u8])], symbols: &[(&str, u32)]) -> Vec<u8> {
+        fn push_name(table: &mut Vec<u8>, name: &str) -> u32 {
+            let index = table.len() as u32;
+            table.extend_from_slice(name.as_bytes());
+            table.push(0);
+            index
+        }
+
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; 16];
+        for (name, value) in symbols {
+            symtab.extend_from_slice(&push_name(&mut strtab, name).to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&[0; 8]);
+        }
+
+        // (name, type, address, data), types are 1 for progbits, 2 for symtab and 3 for strtab
+        let mut shstrtab = vec![0];
+        let mut all = Vec::new();
+        for (name, address, data) in sections {
+            all.push((push_name(&mut shstrtab, name), 1, *address, data.to_vec()));
+        }
+        all.push((push_name(&mut shstrtab, ".symtab"), 2, 0, symtab));
+        all.push((push_name(&mut shstrtab, ".strtab"), 3, 0, strtab));
+        let name = push_name(&mut shstrtab, ".shstrtab");
+        all.push((name, 3, 0, shstrtab));
+
+        let mut elf = vec![0; 52];
+        let mut headers = vec![0; 40];
+        for (name, kind, address, data) in all.iter() {
+            elf.resize((elf.len() + 3) & !3, 0);
+            for field in &[
+                *name,
+                *kind,
+                0,
+                *address,
+                elf.len() as u32,
+                data.len() as u32,
+                0,
+                0,
+                4,
+                if *kind == 2 { 16 } else { 0 },
+            ] {
+                headers.extend_from_slice(&field.to_le_bytes());
+            }
+            elf.extend_from_slice(data);
+        }
+        elf.resize((elf.len() + 3) & !3, 0);
+        let section_offset = elf.len() as u32;
+        elf.extend_from_slice(&headers);
+
+        elf[0..8].copy_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1, 1, 0]);
+        elf[16..18].copy_from_slice(&2u16.to_le_bytes()); // Executable
+        elf[18..20].copy_from_slice(&40u16.to_le_bytes()); // Arm
+        elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+        elf[32..36].copy_from_slice(&section_offset.to_le_bytes());
+        elf[40..42].copy_from_slice(&52u16.to_le_bytes());
+        elf[42..44].copy_from_slice(&32u16.to_le_bytes());
+        elf[46..48].copy_from_slice(&40u16.to_le_bytes());
+        elf[48..50].copy_from_slice(&(all.len() as u16 + 1).to_le_bytes());
+        elf[50..52].copy_from_slice(&(all.len() as u16).to_le_bytes());
+        elf
+    }
+
+    fn header() -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        header[0xA0..0xAC].copy_from_slice(b"MAKEROM TEST");
+        header[0xAC..0xB2].copy_from_slice(b"AMRT01");
+        header[0xB2] = 0x96;
+        header
+    }
+
+    fn build(sections: &[(&str, u32, &[u8])], symbols: &[(&str, u32)]) -> Result<Vec<u8>, Error> {
+        let elf = elf_file(sections, symbols);
+        build_rom(&ElfFile::new(&elf)?)
+    }
+
+    const LOAD_SYMBOLS: &[(&str, u32)] = &[
+        ("__load_modify_begin", 0x8000104),
+        ("__load_fast_mem_begin", 0x8000108),
+    ];
+
+    #[test]
+    fn sections_by_address() {
+        let header = header();
+        let read_only = [1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0];
+        let rom = build(
+            &[
+                ("fast_mem", 0x3000000, &[7, 8, 9]),
+                ("read_only", 0x8000100, &read_only),
+                ("modify", 0x2000000, &[5, 6]),
+                ("exidx", 0x800010C, &[10]),
+                ("header", 0x8000000, &header),
+            ],
+            LOAD_SYMBOLS,
+        )
+        .unwrap();
+
+        assert_eq!(rom.len(), MIN_ROM_SIZE);
+        assert_eq!(rom[0..0xA0], header[0..0xA0]);
+        assert_eq!(rom[0xA0..0xBD], header[0xA0..0xBD]);
+        assert!(rom[HEADER_SIZE..0x100].iter().all(|byte| *byte == 0));
+        assert_eq!(rom[0x100..0x104], [1, 2, 3, 4]);
+        assert_eq!(rom[0x10C], 10);
+        // Modify goes after exidx, fast_mem after modify, both 4 byte aligned
+        assert_eq!(rom[0x104..0x108], 0x8000110u32.to_le_bytes());
+        assert_eq!(rom[0x110..0x112], [5, 6]);
+        assert_eq!(rom[0x108..0x10C], 0x8000114u32.to_le_bytes());
+        assert_eq!(rom[0x114..0x117], [7, 8, 9]);
+        assert!(rom[0x117..].iter().all(|byte| *byte == 0));
+    }
+
+    // The header and a read only section, with empty ram sections
+    fn build_read_only(
+        address: u32,
+        read_only: &[u8],
+        symbols: &[(&str, u32)],
+    ) -> Result<Vec<u8>, Error> {
+        build(
+            &[
+                ("header", 0x8000000, &header()),
+                ("read_only", address, read_only),
+                ("modify", 0x2000000, &[]),
+                ("fast_mem", 0x3000000, &[]),
+            ],
+            symbols,
+        )
+    }
+
+    #[test]
+    fn complement_check() {
+        let rom = build_read_only(0x8000100, &[0; 12], LOAD_SYMBOLS).unwrap();
+        let sum = rom[0xA0..=0xBD]
+            .iter()
+            .fold(0x19u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(sum, 0);
+        assert_eq!(header_checksum(&[0; HEADER_SIZE]), 0xE7);
+    }
+
+    #[test]
+    fn padding() {
+        for (size, padded) in &[
+            (1, MIN_ROM_SIZE),
+            (MIN_ROM_SIZE, MIN_ROM_SIZE),
+            (MIN_ROM_SIZE + 1, 2 * MIN_ROM_SIZE),
+            (5 * 1024 * 1024, 8 * 1024 * 1024),
+            (MAX_ROM_SIZE, MAX_ROM_SIZE),
+        ] {
+            let mut rom = vec![1; *size];
+            pad_rom(&mut rom).unwrap();
+            assert_eq!(rom.len(), *padded);
+            assert!(rom[*size..].iter().all(|byte| *byte == 0));
+        }
+        let mut rom = vec![0; MAX_ROM_SIZE + 1];
+        assert!(matches!(pad_rom(&mut rom), Err(Error::TooBig(_))));
+    }
+
+    #[test]
+    fn bad_sections() {
+        let missing = build(
+            &[
+                ("header", 0x8000000, &header()),
+                ("modify", 0x2000000, &[]),
+                ("fast_mem", 0x3000000, &[]),
+            ],
+            LOAD_SYMBOLS,
+        );
+        assert!(matches!(missing, Err(Error::MissingSection("read_only"))));
+
+        let in_ram = build_read_only(0x3000000, &[0; 12], LOAD_SYMBOLS);
+        assert!(matches!(
+            in_ram,
+            Err(Error::NotInRom("read_only", 0x3000000))
+        ));
+
+        let overlap = build_read_only(0x80000BC, &[0; 12], LOAD_SYMBOLS);
+        assert!(matches!(
+            overlap,
+            Err(Error::Overlap("read_only", "header"))
+        ));
+
+        let past_end = build_read_only(0x9FFFFFC, &[0; 12], LOAD_SYMBOLS);
+        assert!(matches!(past_end, Err(Error::TooBig(_))));
+
+        let no_symbol = build_read_only(0x8000100, &[0; 12], &LOAD_SYMBOLS[..1]);
+        assert!(matches!(
+            no_symbol,
+            Err(Error::MissingSymbol("__load_fast_mem_begin"))
+        ));
+
+        let symbol_outside = build_read_only(0x8000100, &[0; 4], LOAD_SYMBOLS);
+        assert!(matches!(
+            symbol_outside,
+            Err(Error::NotInRom("__load_modify_begin", 0x8000104))
+        ));
+    }
+}